@@ -0,0 +1,200 @@
+//! Conversion between laid out lines and text containing ANSI escape sequences.
+
+use crate::graphics::{self, ImageProtocol};
+use crate::layout::{Line, Span, SpanKind, placements};
+use crate::palette::{self, ColorDepth};
+use ansi_term::{Colour, Style};
+use console::AnsiCodeIterator;
+use std::io::{self, Write};
+
+/// Writes the lines to `out`, styling each span with ANSI escape sequences for a terminal of this
+/// colour depth.
+pub fn write_lines<W: Write>(lines: &[Line], depth: ColorDepth, out: &mut W) -> io::Result<()> {
+    for line in lines {
+        write_line(line, depth, out)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes the lines to `out` like `write_lines`, but draws each image with the graphics protocol,
+/// over the cells that its pixels would have taken. The cells are `cell_size` pixels each.
+pub fn write_lines_with_images<W: Write>(
+    lines: &[Line],
+    protocol: ImageProtocol,
+    cell_size: (u32, u32),
+    depth: ColorDepth,
+    out: &mut W,
+) -> io::Result<()> {
+    if protocol == ImageProtocol::Halfblock {
+        return write_lines(lines, depth, out);
+    }
+    let placements = placements(lines);
+    for (index, line) in lines.iter().enumerate() {
+        let mut column = 0;
+        let mut spans = line.spans.iter().peekable();
+        while let Some(span) = spans.next() {
+            let start = column;
+            column += span.width();
+            if span.kind != SpanKind::Image {
+                write_styled(span.style, &span.text, depth, out)?;
+                continue;
+            }
+            while let Some(span) = spans.next_if(|span| span.kind == SpanKind::Image) {
+                column += span.width();
+            }
+            let cells = " ".repeat(column - start);
+            let Some(placement) = placements.iter().find(|placement| {
+                placement.column == start
+                    && (placement.line..placement.line + placement.visible_rows()).contains(&index)
+            }) else {
+                write!(out, "{}", cells)?;
+                continue;
+            };
+            // Kitty leaves the cursor where it was, so the image is drawn from its first row. The
+            // others move the cursor, and would scroll the terminal if the image went past the
+            // bottom, so they are drawn once the cells of the last row are written, and then the
+            // cursor is put back.
+            if protocol == ImageProtocol::Kitty && index == placement.line {
+                write!(out, "{}", graphics::draw(placement, protocol, cell_size))?;
+            }
+            write!(out, "{}", cells)?;
+            if protocol != ImageProtocol::Kitty
+                && index + 1 == placement.line + placement.visible_rows()
+            {
+                write!(out, "\x1b7")?;
+                if placement.visible_rows() > 1 {
+                    write!(out, "\x1b[{}A", placement.visible_rows() - 1)?;
+                }
+                write!(
+                    out,
+                    "\x1b[{}D{}\x1b8",
+                    column - start,
+                    graphics::draw(placement, protocol, cell_size)
+                )?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes a single line to `out`, without the trailing newline.
+pub fn write_line<W: Write>(line: &Line, depth: ColorDepth, out: &mut W) -> io::Result<()> {
+    for span in &line.spans {
+        write_styled(span.style, &span.text, depth, out)?;
+    }
+    Ok(())
+}
+
+/// Writes the text in the style. For terminals that only show 16 colours, those colours are written
+/// with their own codes, as `ansi_term` would write the bright ones as indices of the 256 colours,
+/// which those terminals don't understand.
+fn write_styled<W: Write>(
+    style: Style,
+    text: &str,
+    depth: ColorDepth,
+    out: &mut W,
+) -> io::Result<()> {
+    if depth != ColorDepth::Ansi16 {
+        return write!(out, "{}", style.paint(text));
+    }
+    let basic = |colour| match colour {
+        Some(Colour::Fixed(index @ 0..16)) => Some(index),
+        _ => None,
+    };
+    let (foreground, background) = (basic(style.foreground), basic(style.background));
+    let rest = Style {
+        foreground: style.foreground.filter(|_| foreground.is_none()),
+        background: style.background.filter(|_| background.is_none()),
+        ..style
+    };
+    write!(out, "{}", rest.prefix())?;
+    if let Some(index) = foreground {
+        write!(out, "\x1b[{}m", palette::sgr(index, false))?;
+    }
+    if let Some(index) = background {
+        write!(out, "\x1b[{}m", palette::sgr(index, true))?;
+    }
+    write!(out, "{}{}", text, style.suffix())
+}
+
+/// Splits a line of text containing ANSI escape sequences into styled spans. Each escape sequence
+/// is applied on top of the `base` style, and a reset returns to the `base` style.
+pub fn parse(text: &str, base: Style, kind: SpanKind) -> Vec<Span> {
+    let mut spans = vec![];
+    let mut style = base;
+    for (s, is_ansi) in AnsiCodeIterator::new(text) {
+        if !is_ansi {
+            if !s.is_empty() {
+                spans.push(Span::new(s, style, kind));
+            }
+        } else if let Some(params) = s
+            .strip_prefix("\x1b[")
+            .and_then(|code| code.strip_suffix('m'))
+        {
+            style = apply_sgr(style, base, params);
+        }
+    }
+    spans
+}
+
+fn apply_sgr(mut style: Style, base: Style, params: &str) -> Style {
+    let mut params = params
+        .split(';')
+        .map(|param| param.parse::<u8>().unwrap_or(0));
+    while let Some(param) = params.next() {
+        match param {
+            0 => style = base,
+            1 => style.is_bold = true,
+            2 => style.is_dimmed = true,
+            3 => style.is_italic = true,
+            4 => style.is_underline = true,
+            5 => style.is_blink = true,
+            7 => style.is_reverse = true,
+            8 => style.is_hidden = true,
+            9 => style.is_strikethrough = true,
+            22 => {
+                style.is_bold = false;
+                style.is_dimmed = false;
+            }
+            23 => style.is_italic = false,
+            24 => style.is_underline = false,
+            25 => style.is_blink = false,
+            27 => style.is_reverse = false,
+            28 => style.is_hidden = false,
+            29 => style.is_strikethrough = false,
+            30..=37 => style.foreground = Some(basic_colour(param - 30)),
+            38 => style.foreground = extended_colour(&mut params).or(style.foreground),
+            39 => style.foreground = base.foreground,
+            40..=47 => style.background = Some(basic_colour(param - 40)),
+            48 => style.background = extended_colour(&mut params).or(style.background),
+            49 => style.background = base.background,
+            90..=97 => style.foreground = Some(Colour::Fixed(param - 90 + 8)),
+            100..=107 => style.background = Some(Colour::Fixed(param - 100 + 8)),
+            _ => {}
+        }
+    }
+    style
+}
+
+fn basic_colour(index: u8) -> Colour {
+    match index {
+        0 => Colour::Black,
+        1 => Colour::Red,
+        2 => Colour::Green,
+        3 => Colour::Yellow,
+        4 => Colour::Blue,
+        5 => Colour::Purple,
+        6 => Colour::Cyan,
+        _ => Colour::White,
+    }
+}
+
+fn extended_colour(params: &mut impl Iterator<Item = u8>) -> Option<Colour> {
+    match params.next()? {
+        5 => Some(Colour::Fixed(params.next()?)),
+        2 => Some(Colour::RGB(params.next()?, params.next()?, params.next()?)),
+        _ => None,
+    }
+}
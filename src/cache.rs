@@ -0,0 +1,208 @@
+//! Keeps the output of syncat and filters in the user's cache directory, so that printing the same
+//! document again, such as in watch or pager mode, doesn't have to run them again. Each output is
+//! stored in a file named after a hash of everything that goes into it, and begins with everything
+//! that went into it, so that two blocks whose hashes collide are never given each other's output.
+
+use crate::dirs;
+use crate::filter::Block;
+use fnv::FnvHasher;
+use std::fs;
+use std::hash::Hasher as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use syncat_stylesheet::Stylesheet;
+
+pub struct Cache {
+    dir: PathBuf,
+    /// A hash of the stylesheets that the output depends on.
+    stylesheet: u64,
+}
+
+impl Cache {
+    /// A cache for output styled by this stylesheet, and the active syncat theme.
+    pub fn new(stylesheet: &Stylesheet) -> Self {
+        Self::in_dir(dirs::paper_cache(), &dirs::active_color(), stylesheet)
+    }
+
+    /// A cache kept in `dir`, for output styled by this stylesheet, and the syncat theme in `theme`.
+    fn in_dir(dir: PathBuf, theme: &Path, stylesheet: &Stylesheet) -> Self {
+        let mut hasher = FnvHasher::default();
+        hasher.write(format!("{:?}", stylesheet).as_bytes());
+        let mut theme = fs::read_dir(theme)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        theme.sort();
+        for path in theme {
+            hasher.write(path.to_string_lossy().as_bytes());
+            hasher.write(&fs::read(path).unwrap_or_default());
+        }
+        Self {
+            dir,
+            stylesheet: hasher.finish(),
+        }
+    }
+
+    /// Everything that goes into the output for the block. Each part is written with its length, so
+    /// that no key is the start of another.
+    fn key(&self, block: &Block) -> String {
+        let mut key = format!(
+            "{:016x} {} {}\n",
+            self.stylesheet,
+            block.width,
+            block.command.len()
+        );
+        for part in block.command.iter().chain([&block.code]) {
+            key.push_str(&format!("{}:{}\n", part.len(), part));
+        }
+        key
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut hasher = FnvHasher::default();
+        hasher.write(key.as_bytes());
+        self.dir.join(format!("{:016x}", hasher.finish()))
+    }
+
+    /// The output for the block, if it has been cached.
+    pub fn get(&self, block: &Block) -> Option<String> {
+        let key = self.key(block);
+        let contents = fs::read_to_string(self.path(&key)).ok()?;
+        contents.strip_prefix(&key).map(str::to_owned)
+    }
+
+    /// Caches the output for the block. The output is written to a temporary file first, so that
+    /// other papers being printed at the same time never see it half written.
+    pub fn put(&self, block: &Block, output: &str) -> io::Result<()> {
+        let key = self.key(block);
+        let path = self.path(&key);
+        let temporary = path.with_extension(std::process::id().to_string());
+        fs::create_dir_all(&self.dir)?;
+        fs::write(&temporary, key + output)?;
+        fs::rename(temporary, path)
+    }
+}
+
+/// Removes everything from the cache.
+pub fn clear() -> io::Result<()> {
+    remove(&dirs::paper_cache())
+}
+
+fn remove(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Outputs;
+
+    /// An empty directory for a test to keep its cache and theme in.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("paper-{}-{}", std::process::id(), name));
+        remove(&dir).unwrap();
+        fs::create_dir_all(dir.join("theme")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn entries_are_keyed_by_everything_that_goes_into_them() {
+        let dir = temp_dir("cache-key");
+        let theme = dir.join("theme");
+        let stylesheet = crate::default_stylesheet();
+        let cache = Cache::in_dir(dir.join("cache"), &theme, &stylesheet);
+        let block = Block::syncat("rust", "fn main() {}", 40);
+        assert_eq!(cache.get(&block), None);
+        cache.put(&block, "highlighted").unwrap();
+        assert_eq!(cache.get(&block).as_deref(), Some("highlighted"));
+
+        assert_eq!(cache.get(&Block::syncat("rust", "fn main() {}", 60)), None);
+        assert_eq!(cache.get(&Block::syncat("rust", "fn other() {}", 40)), None);
+        assert_eq!(cache.get(&Block::syncat("toml", "fn main() {}", 40)), None);
+
+        let restyled = "code { color: red; }".parse::<Stylesheet>().unwrap();
+        let restyled = Cache::in_dir(dir.join("cache"), &theme, &restyled);
+        assert_eq!(restyled.get(&block), None);
+
+        fs::write(theme.join("rust.syncat"), "keyword { color: red; }").unwrap();
+        let rethemed = Cache::in_dir(dir.join("cache"), &theme, &stylesheet);
+        assert_eq!(rethemed.get(&block), None);
+    }
+
+    #[test]
+    fn colliding_entries_are_not_mixed_up() {
+        let dir = temp_dir("cache-collision");
+        let cache = Cache::in_dir(
+            dir.join("cache"),
+            &dir.join("theme"),
+            &Stylesheet::default(),
+        );
+        let (block, other) = (
+            Block::syncat("rust", "fn main() {}", 40),
+            Block::syncat("rust", "fn other() {}", 40),
+        );
+        cache.put(&other, "other").unwrap();
+        // As if the two blocks' hashes were the same.
+        fs::rename(
+            cache.path(&cache.key(&other)),
+            cache.path(&cache.key(&block)),
+        )
+        .unwrap();
+        assert_eq!(cache.get(&block), None);
+    }
+
+    #[test]
+    fn clearing_removes_every_entry() {
+        let dir = temp_dir("cache-clear");
+        let cache = Cache::in_dir(
+            dir.join("cache"),
+            &dir.join("theme"),
+            &Stylesheet::default(),
+        );
+        let block = Block::syncat("rust", "fn main() {}", 40);
+        cache.put(&block, "highlighted").unwrap();
+        remove(&cache.dir).unwrap();
+        assert_eq!(cache.get(&block), None);
+        // There is nothing to clear the second time, which is fine.
+        remove(&cache.dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_are_only_run_again_without_the_cache() {
+        let dir = temp_dir("cache-runs");
+        let runs = dir.join("runs");
+        let command = format!("echo run >> '{}'; cat", runs.display());
+        let block = Block::shell(&command, "code", 40);
+        let cache = || {
+            Cache::in_dir(
+                dir.join("cache"),
+                &dir.join("theme"),
+                &Stylesheet::default(),
+            )
+        };
+        let run_count = || {
+            fs::read_to_string(&runs)
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+
+        for _ in 0..2 {
+            let mut outputs = Outputs::cached(cache());
+            outputs.prefetch([block.clone()]);
+            assert_eq!(outputs.get(&block), Some("code"));
+        }
+        assert_eq!(run_count(), 1);
+
+        let mut outputs = Outputs::default();
+        outputs.prefetch([block.clone()]);
+        assert_eq!(outputs.get(&block), Some("code"));
+        assert_eq!(run_count(), 2);
+    }
+}
@@ -5,6 +5,18 @@ fn syncat_directories() -> ProjectDirs {
     ProjectDirs::from("com", "cameldridge", "syncat").unwrap()
 }
 
+fn paper_directories() -> ProjectDirs {
+    ProjectDirs::from("com", "cameldridge", "paper").unwrap()
+}
+
+pub fn paper_config() -> PathBuf {
+    paper_directories().config_dir().to_owned()
+}
+
+pub fn paper_cache() -> PathBuf {
+    paper_directories().cache_dir().to_owned()
+}
+
 pub fn syncat_config() -> PathBuf {
     syncat_directories().config_dir().to_owned()
 }
@@ -0,0 +1,243 @@
+//! Filters code blocks through external commands, such as syncat for highlighting. All the code
+//! blocks of a document are found before it is printed, and then filtered in parallel, so that
+//! documents with many code blocks don't have to wait for each one in turn.
+
+use crate::cache::Cache;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read as _, Write as _};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a command to filter a code block before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A code block to be filtered through a command, at the width it is printed at.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Block {
+    /// The program to run, followed by its arguments.
+    pub command: Vec<String>,
+    /// How the command is described in error messages.
+    pub name: String,
+    pub code: String,
+    pub width: usize,
+}
+
+impl Block {
+    /// A block to be filtered through a command line, which is run by the shell.
+    pub fn shell(command_line: &str, code: &str, width: usize) -> Self {
+        let shell = if cfg!(windows) {
+            ["cmd", "/C"]
+        } else {
+            ["sh", "-c"]
+        };
+        Self {
+            command: shell
+                .into_iter()
+                .map(str::to_owned)
+                .chain([command_line.to_owned()])
+                .collect(),
+            name: command_line.to_owned(),
+            code: code.to_owned(),
+            width,
+        }
+    }
+
+    /// A block to be highlighted by syncat.
+    pub fn syncat(lang: &str, code: &str, width: usize) -> Self {
+        let command: Vec<_> = ["syncat", "-l", lang, "-w", &width.to_string()]
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        Self {
+            name: command.join(" "),
+            command,
+            code: code.to_owned(),
+            width,
+        }
+    }
+}
+
+/// The output of the command for each code block that has been filtered.
+#[derive(Default)]
+pub struct Outputs {
+    results: HashMap<Block, Option<String>>,
+    cache: Option<Cache>,
+}
+
+impl Outputs {
+    /// Outputs that are read from the cache when they can be, and written to it when they can't.
+    pub fn cached(cache: Cache) -> Self {
+        Self {
+            cache: Some(cache),
+            ..Self::default()
+        }
+    }
+
+    /// The filtered code, or `None` if the block could not be filtered, or was never prefetched.
+    pub fn get(&self, block: &Block) -> Option<&str> {
+        self.results.get(block)?.as_deref()
+    }
+
+    /// Filters all the blocks that have not been filtered yet, in parallel.
+    pub fn prefetch(&mut self, blocks: impl IntoIterator<Item = Block>) {
+        let blocks = blocks
+            .into_iter()
+            .filter(|block| !self.results.contains_key(block))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter(|block| {
+                let cached = self.cache.as_ref().and_then(|cache| cache.get(block));
+                let Some(output) = cached else {
+                    return true;
+                };
+                self.results.insert(block.clone(), Some(output));
+                false
+            })
+            .collect::<Vec<_>>();
+        if blocks.is_empty() {
+            return;
+        }
+        let threads = thread::available_parallelism().map_or(4, usize::from);
+        let results = thread::scope(|scope| {
+            blocks
+                .chunks(blocks.len().div_ceil(threads))
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|block| (block.clone(), run(block, TIMEOUT)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let mut errors = vec![];
+        for (block, result) in results {
+            let output = result
+                .inspect_err(|error| {
+                    if !errors.contains(&error.to_string()) {
+                        errors.push(error.to_string());
+                    }
+                })
+                .ok();
+            if let (Some(cache), Some(output)) = (&self.cache, &output) {
+                cache.put(&block, output).ok();
+            }
+            self.results.insert(block, output);
+        }
+        for error in errors {
+            eprintln!("{}", error);
+        }
+    }
+}
+
+/// Runs the command on the block, killing it if it takes longer than the timeout. The code is
+/// written to the command while its output is being read, so that neither side blocks on a full
+/// pipe. The width is passed on in `COLUMNS`, for commands that wrap their output.
+fn run(block: &Block, timeout: Duration) -> io::Result<String> {
+    let mut child = Command::new(&block.command[0])
+        .args(&block.command[1..])
+        .env("COLUMNS", block.width.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|error| {
+            io::Error::new(error.kind(), format!("{}: {}", block.command[0], error))
+        })?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+
+    let code = block.code.clone();
+    thread::spawn(move || stdin.write_all(code.as_bytes()));
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = String::new();
+        let result = stdout.read_to_string(&mut output).map(|_| output);
+        sender.send(result).ok();
+    });
+
+    let deadline = Instant::now() + timeout;
+    let timed_out = |child: &mut Child| {
+        child.kill().ok();
+        child.wait().ok();
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("`{}` took too long to filter a code block", block.name),
+        )
+    };
+    let Ok(output) = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+    else {
+        return Err(timed_out(&mut child));
+    };
+    // The command may close its output and keep running, so it is held to the same deadline.
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            return Err(timed_out(&mut child));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "`{}` failed to filter a code block ({})",
+            block.name, status
+        )));
+    }
+    output
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_commands_are_killed() {
+        let block = Block::shell("sleep 20", "", 80);
+        let start = Instant::now();
+        let error = run(&block, Duration::from_millis(200)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn commands_that_close_their_output_are_still_killed() {
+        let block = Block::shell("exec >&-; sleep 20", "", 80);
+        let start = Instant::now();
+        let error = run(&block, Duration::from_millis(200)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn failed_commands_leave_the_code_unfiltered() {
+        let block = Block::shell("echo partial; false", "code", 80);
+        assert!(run(&block, TIMEOUT).is_err());
+
+        let mut outputs = Outputs::default();
+        outputs.prefetch([block.clone()]);
+        assert_eq!(outputs.get(&block), None);
+    }
+
+    #[test]
+    fn large_blocks_do_not_fill_the_pipes() {
+        // Much more than fits in a pipe's buffer, so writing it all before reading would deadlock.
+        let code = "0123456789abcdef\n".repeat(1 << 16);
+        let block = Block::shell("cat", &code, 80);
+        assert_eq!(run(&block, TIMEOUT).unwrap(), code);
+    }
+
+    #[test]
+    fn the_width_is_passed_on() {
+        let block = Block::shell("echo $COLUMNS", "", 42);
+        assert_eq!(run(&block, TIMEOUT).unwrap(), "42\n");
+    }
+}
@@ -0,0 +1,236 @@
+//! Draws images with the graphics protocols of terminals that have them, in place of the pixels
+//! that images are otherwise drawn with. Each image is drawn over the cells its pixels would take
+//! up, so it stays within the paper.
+
+use crate::layout::Placement;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::fmt::Write as _;
+use std::io::Cursor;
+
+/// How images are drawn in the terminal.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ImageProtocol {
+    /// As pixels made of half blocks, which works in any terminal with colour.
+    #[default]
+    Halfblock,
+    /// With the kitty graphics protocol.
+    Kitty,
+    /// As iTerm2 inline images.
+    Iterm,
+    /// As sixels.
+    Sixel,
+}
+
+/// The size of a cell of the terminal, in pixels, when the terminal does not say, or there is no
+/// terminal.
+pub const DEFAULT_CELL_SIZE: (u32, u32) = (10, 20);
+/// The largest piece of a kitty image that may be sent in one escape sequence.
+const KITTY_CHUNK: usize = 4096;
+
+impl ImageProtocol {
+    /// Guesses which protocol the terminal supports from the environment. Terminals that are run
+    /// inside of tmux or screen get half blocks, as those do not pass images through.
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+        if std::env::var_os("TMUX").is_some() || term.starts_with("screen") {
+            Self::Halfblock
+        } else if std::env::var_os("KITTY_WINDOW_ID").is_some()
+            || term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || program == "ghostty"
+        {
+            Self::Kitty
+        } else if program == "iTerm.app" || program == "WezTerm" || var("LC_TERMINAL") == "iTerm2" {
+            Self::Iterm
+        } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") {
+            Self::Sixel
+        } else {
+            Self::Halfblock
+        }
+    }
+
+    /// The escape sequence that draws the image across the given number of cells, starting at the
+    /// cursor. The cells are `cell_size` pixels each.
+    pub fn encode(
+        self,
+        image: &DynamicImage,
+        columns: u32,
+        rows: u32,
+        cell_size: (u32, u32),
+    ) -> String {
+        match self {
+            Self::Halfblock => String::new(),
+            Self::Kitty => kitty(image, columns, rows, cell_size),
+            Self::Iterm => iterm(image, columns, rows, cell_size),
+            Self::Sixel => sixel(image, columns, rows, cell_size),
+        }
+    }
+}
+
+/// The escape sequence that draws the rows of the image that are visible, over the cells that its
+/// pixels take up.
+pub(crate) fn draw(
+    placement: &Placement,
+    protocol: ImageProtocol,
+    cell_size: (u32, u32),
+) -> String {
+    let image = &placement.image;
+    let rows = placement.rows as u32;
+    let top = placement.first_row as u32 * image.height() / rows;
+    let bottom = (placement.last_row as u32 + 1) * image.height() / rows;
+    let visible = image.crop_imm(0, top, image.width(), bottom - top);
+    protocol.encode(
+        &visible,
+        placement.columns as u32,
+        placement.visible_rows() as u32,
+        cell_size,
+    )
+}
+
+/// The size of each cell of the terminal, in pixels, as the terminal says it is.
+pub fn terminal_cell_size() -> (u32, u32) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => (
+            (size.width / size.columns) as u32,
+            (size.height / size.rows) as u32,
+        ),
+        _ => DEFAULT_CELL_SIZE,
+    }
+}
+
+/// Shrinks the image to the number of pixels that the cells have, if it is bigger, so that no more
+/// is sent to the terminal than it can show.
+fn fit(image: &DynamicImage, columns: u32, rows: u32, cell_size: (u32, u32)) -> DynamicImage {
+    let (cell_width, cell_height) = cell_size;
+    let (width, height) = (columns * cell_width, rows * cell_height);
+    if image.width() > width || image.height() > height {
+        image.resize_exact(width, height, FilterType::Triangle)
+    } else {
+        image.clone()
+    }
+}
+
+fn png(image: &DynamicImage) -> String {
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageFormat::Png).unwrap();
+    BASE64.encode(png.into_inner())
+}
+
+/// The image as a PNG, sent in pieces with the kitty graphics protocol. The cursor stays where it
+/// is, rather than moving past the image.
+fn kitty(image: &DynamicImage, columns: u32, rows: u32, cell_size: (u32, u32)) -> String {
+    let data = png(&fit(image, columns, rows, cell_size));
+    let chunks = data.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+    let mut out = String::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        if index == 0 {
+            write!(
+                out,
+                "\x1b_Ga=T,f=100,q=2,C=1,c={columns},r={rows},m={more};"
+            )
+            .unwrap();
+        } else {
+            write!(out, "\x1b_Gm={more};").unwrap();
+        }
+        out.push_str(std::str::from_utf8(chunk).unwrap());
+        out.push_str("\x1b\\");
+    }
+    out
+}
+
+/// The image as a PNG, sent as an iTerm2 inline image, stretched over the cells.
+fn iterm(image: &DynamicImage, columns: u32, rows: u32, cell_size: (u32, u32)) -> String {
+    let data = png(&fit(image, columns, rows, cell_size));
+    format!(
+        "\x1b]1337;File=inline=1;width={columns};height={rows};preserveAspectRatio=0:{data}\x07"
+    )
+}
+
+/// The image in sixels, with its colours rounded to a 6×6×6 cube. Pixels that are mostly
+/// transparent are left undrawn.
+fn sixel(image: &DynamicImage, columns: u32, rows: u32, cell_size: (u32, u32)) -> String {
+    let (cell_width, cell_height) = cell_size;
+    let (width, height) = (columns * cell_width, rows * cell_height);
+    let image = image
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_rgba8();
+    let level = |channel: u8| (channel as u32 * 5 + 127) / 255;
+    let colours = image
+        .pixels()
+        .map(|pixel| {
+            (pixel[3] >= 128).then(|| level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]))
+        })
+        .collect::<Vec<_>>();
+
+    let mut palette = colours.iter().flatten().copied().collect::<Vec<_>>();
+    palette.sort_unstable();
+    palette.dedup();
+
+    let mut out = format!("\x1bP0;1;0q\"1;1;{width};{height}");
+    for colour in palette {
+        let percent = |level: u32| level * 100 / 5;
+        write!(
+            out,
+            "#{colour};2;{};{};{}",
+            percent(colour / 36),
+            percent(colour / 6 % 6),
+            percent(colour % 6)
+        )
+        .unwrap();
+    }
+    for band in (0..height).step_by(6) {
+        let band_rows = u32::min(6, height - band);
+        let mut used = colours[(band * width) as usize..((band + band_rows) * width) as usize]
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        used.sort_unstable();
+        used.dedup();
+        for colour in used {
+            write!(out, "#{colour}").unwrap();
+            let sixels = (0..width).map(|x| {
+                (0..band_rows)
+                    .filter(|y| colours[((band + y) * width + x) as usize] == Some(colour))
+                    .fold(0, |bits, y| bits | 1 << y)
+            });
+            write_run_length(&mut out, sixels);
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Writes the sixels, with each run of the same sixel shortened to its count.
+fn write_run_length(out: &mut String, sixels: impl Iterator<Item = u8>) {
+    let mut run: Option<(u8, usize)> = None;
+    let flush = |out: &mut String, (bits, count): (u8, usize)| {
+        let ch = char::from(b'?' + bits);
+        if count > 3 {
+            write!(out, "!{count}{ch}").unwrap();
+        } else {
+            out.extend(std::iter::repeat_n(ch, count));
+        }
+    };
+    for bits in sixels {
+        match &mut run {
+            Some((run_bits, count)) if *run_bits == bits => *count += 1,
+            _ => {
+                if let Some(run) = run.replace((bits, 1)) {
+                    flush(out, run);
+                }
+            }
+        }
+    }
+    if let Some(run) = run {
+        flush(out, run);
+    }
+}
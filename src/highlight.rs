@@ -0,0 +1,53 @@
+//! Highlights code blocks using grammars compiled into the binary, for when syncat is not
+//! installed. The grammar's scopes are nested within the code block's scopes when styling each
+//! token, so the same `codeblock <lang>` selectors apply as when using syncat.
+
+use crate::layout::{self, Span};
+use crate::printer::resolve_scopes;
+use ansi_term::Style;
+use std::sync::LazyLock;
+use syncat_stylesheet::Stylesheet;
+use syntect::easy::ScopeRangeIterator;
+use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+/// Highlights the code, returning the spans of each line, or `None` if there is no grammar for the
+/// language. Each token is styled by the `scopes` it is nested in, followed by each part of each
+/// of the grammar's scopes (`keyword.control.rust` becomes `keyword control rust`), with the text
+/// of the token as its token.
+pub fn highlight(
+    code: &str,
+    lang: &str,
+    stylesheet: &Stylesheet,
+    scopes: &[&str],
+    base: Style,
+) -> Option<Vec<Vec<Span>>> {
+    let syntax = SYNTAXES.find_syntax_by_token(lang)?;
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut lines = vec![];
+    for line in LinesWithEndings::from(code) {
+        let ops = state.parse_line(line, &SYNTAXES).ok()?;
+        let mut spans = vec![];
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            stack.apply(op).ok()?;
+            let text = line[range].trim_end_matches(['\n', '\r']);
+            if text.is_empty() {
+                continue;
+            }
+            let grammar_scopes = stack
+                .as_slice()
+                .iter()
+                .map(|scope| scope.build_string())
+                .collect::<Vec<_>>();
+            let mut names = scopes.to_vec();
+            names.extend(grammar_scopes.iter().flat_map(|scope| scope.split('.')));
+            let style = layout::overlay(base, resolve_scopes(stylesheet, &names, Some(text)));
+            spans.push(Span::content(text, style));
+        }
+        lines.push(spans);
+    }
+    Some(lines)
+}
@@ -0,0 +1,214 @@
+//! Writes laid out lines as a standalone HTML page, which looks the same as the paper does in the
+//! terminal: each line is a row of a monospace grid, styled with the colours the terminal would
+//! show. Images are embedded as data URIs, at the resolution of the cells they cover rather than of
+//! their half block pixels.
+
+use crate::layout::{self, Line, SpanKind};
+use crate::palette;
+use crate::palette::{BACKGROUND, FOREGROUND};
+use ansi_term::Style;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::ImageFormat;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Cursor, Write};
+use std::sync::Arc;
+
+/// The height of each line, in terms of the font size. The width of a character is close to half
+/// of this, so the pixels of images come out square, as they do in the terminal.
+const LINE_HEIGHT: f64 = 1.2;
+
+/// Writes the lines as an HTML page with the given title.
+pub fn write_document<W: Write>(lines: &[Line], title: &str, out: &mut W) -> io::Result<()> {
+    let images = Images::find(lines);
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape(title))?;
+    writeln!(out, "<style>")?;
+    writeln!(
+        out,
+        "body {{ margin: 0; background: {}; color: {}; }}",
+        palette::hex(palette::rgb(BACKGROUND)),
+        palette::hex(palette::rgb(FOREGROUND)),
+    )?;
+    writeln!(
+        out,
+        "pre {{ margin: 1em; font-family: ui-monospace, Menlo, Consolas, monospace; line-height: {}; }}",
+        LINE_HEIGHT,
+    )?;
+    writeln!(
+        out,
+        ".image {{ display: inline-block; position: relative; vertical-align: top; height: {}em; }}",
+        LINE_HEIGHT,
+    )?;
+    writeln!(
+        out,
+        ".image img {{ position: absolute; left: 0; width: 100%; }}",
+    )?;
+    writeln!(out, "</style>")?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    write!(out, "<pre>")?;
+    for (row, line) in lines.iter().enumerate() {
+        write_line(line, row, &images, out)?;
+        writeln!(out)?;
+    }
+    writeln!(out, "</pre>")?;
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+    Ok(())
+}
+
+fn write_line<W: Write>(line: &Line, row: usize, images: &Images, out: &mut W) -> io::Result<()> {
+    let mut column = 0;
+    let mut run: Option<(Style, String)> = None;
+    let mut spans = line.spans.iter().peekable();
+    while let Some(span) = spans.next() {
+        if span.kind == SpanKind::Image {
+            let start = column;
+            column += span.width();
+            while let Some(span) = spans.next_if(|span| span.kind == SpanKind::Image) {
+                column += span.width();
+            }
+            if let Some((style, text)) = run.take() {
+                write_run(style, &text, out)?;
+            }
+            let width = column - start;
+            match images.starting.get(&(row, start)) {
+                // Rows of the image that are cut off, such as by the edge of a sheet, are clipped.
+                Some(image) => write!(
+                    out,
+                    "<span class=\"image\" style=\"width: {}ch\"><img src=\"{}\" style=\"top: -{:.1}em; height: {:.1}em; clip-path: inset({:.1}em 0 {:.1}em 0)\"></span>",
+                    width,
+                    images.uris[image.uri],
+                    image.first_row as f64 * LINE_HEIGHT,
+                    image.rows as f64 * LINE_HEIGHT,
+                    image.first_row as f64 * LINE_HEIGHT,
+                    (image.rows - image.last_row - 1) as f64 * LINE_HEIGHT,
+                )?,
+                None => write!(out, "{}", " ".repeat(width))?,
+            }
+            continue;
+        }
+        column += span.width();
+        match &mut run {
+            Some((style, text)) if *style == span.style => text.push_str(&span.text),
+            _ => {
+                if let Some((style, text)) = run.replace((span.style, span.text.clone())) {
+                    write_run(style, &text, out)?;
+                }
+            }
+        }
+    }
+    if let Some((style, text)) = run {
+        write_run(style, &text, out)?;
+    }
+    Ok(())
+}
+
+fn write_run<W: Write>(style: Style, text: &str, out: &mut W) -> io::Result<()> {
+    let css = declarations(style);
+    if css.is_empty() {
+        write!(out, "{}", escape(text))
+    } else {
+        write!(out, "<span style=\"{}\">{}</span>", css, escape(text))
+    }
+}
+
+/// The CSS that styles text like the terminal would.
+fn declarations(style: Style) -> String {
+    let (foreground, background) = palette::colours(style);
+    let mut css = String::new();
+    if let Some(foreground) = foreground {
+        write!(css, "color: {}; ", palette::hex(foreground)).unwrap();
+    }
+    if let Some(background) = background {
+        write!(css, "background: {}; ", palette::hex(background)).unwrap();
+    }
+    if style.is_bold {
+        css.push_str("font-weight: bold; ");
+    }
+    if style.is_italic {
+        css.push_str("font-style: italic; ");
+    }
+    if let Some(decoration) = text_decoration(style) {
+        write!(css, "text-decoration: {}; ", decoration).unwrap();
+    }
+    css.trim_end().to_owned()
+}
+
+/// The lines drawn through the text, as CSS and SVG expect them.
+pub(crate) fn text_decoration(style: Style) -> Option<&'static str> {
+    match (style.is_underline, style.is_strikethrough) {
+        (true, true) => Some("underline line-through"),
+        (true, false) => Some("underline"),
+        (false, true) => Some("line-through"),
+        (false, false) => None,
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Where an image is drawn, and which of its rows are visible there.
+struct Image {
+    /// The index of the image's data URI.
+    uri: usize,
+    first_row: usize,
+    last_row: usize,
+    rows: usize,
+}
+
+/// The images drawn on the lines, by the line and column that each starts at. Each image is encoded
+/// once, however many times it is drawn.
+struct Images {
+    starting: HashMap<(usize, usize), Image>,
+    uris: Vec<String>,
+}
+
+impl Images {
+    fn find(lines: &[Line]) -> Self {
+        let mut encoded = vec![];
+        let mut uris = vec![];
+        let mut starting = HashMap::new();
+        for placement in layout::placements(lines) {
+            let uri = match encoded
+                .iter()
+                .position(|image| Arc::ptr_eq(image, &placement.image))
+            {
+                Some(index) => index,
+                None => {
+                    let mut png = Cursor::new(vec![]);
+                    placement
+                        .image
+                        .to_rgba8()
+                        .write_to(&mut png, ImageFormat::Png)
+                        .unwrap();
+                    uris.push(format!(
+                        "data:image/png;base64,{}",
+                        BASE64.encode(png.into_inner())
+                    ));
+                    encoded.push(placement.image.clone());
+                    uris.len() - 1
+                }
+            };
+            starting.insert(
+                (placement.line, placement.column),
+                Image {
+                    uri,
+                    first_row: placement.first_row,
+                    last_row: placement.last_row,
+                    rows: placement.rows,
+                },
+            );
+        }
+        Self { starting, uris }
+    }
+}
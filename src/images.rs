@@ -0,0 +1,234 @@
+//! Finds the images that documents refer to. Paths are relative to the document, rather than to
+//! wherever paper is run from, and images may also be given as `file://` URLs, or embedded in the
+//! document as `data:` URIs. SVG images are drawn from their shapes at the size they take up on
+//! the paper, so they stay sharp, rather than being scaled like other images.
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult, RgbaImage};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{self, fontdb};
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+/// An image that a document refers to.
+pub enum Image {
+    /// An image made of pixels.
+    Raster(DynamicImage),
+    /// An SVG image, made of shapes.
+    Vector(Box<usvg::Tree>),
+}
+
+impl Image {
+    /// The size of the image, in pixels. SVG images are as big as they say they are.
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Self::Raster(image) => (image.width(), image.height()),
+            Self::Vector(tree) => {
+                let size = tree.size().to_int_size();
+                (size.width(), size.height())
+            }
+        }
+    }
+
+    /// The image in pixels. SVG images are drawn at the given size, and other images are left at
+    /// their own size, to be resampled when they are drawn.
+    pub fn rasterize(&self, width: u32, height: u32) -> Cow<'_, DynamicImage> {
+        match self {
+            Self::Raster(image) => Cow::Borrowed(image),
+            Self::Vector(tree) => Cow::Owned(draw(tree, width, height)),
+        }
+    }
+
+    /// Like `rasterize`, but without copying images that are already made of pixels.
+    pub fn into_raster(self, width: u32, height: u32) -> DynamicImage {
+        match self {
+            Self::Raster(image) => image,
+            Self::Vector(tree) => draw(&tree, width, height),
+        }
+    }
+}
+
+/// The file an image refers to, relative to the directory of the document it is in. Documents read
+/// from standard input have no directory, so their images are relative to the working directory.
+/// Images embedded in the document have no file.
+pub fn path(dest_url: &str, base: Option<&Path>) -> Option<PathBuf> {
+    if dest_url.starts_with("data:") {
+        return None;
+    }
+    let path = match dest_url.strip_prefix("file://") {
+        Some(url) => {
+            let url = url.strip_prefix("localhost").unwrap_or(url);
+            PathBuf::from(String::from_utf8_lossy(&percent_decode(url)).into_owned())
+        }
+        None => PathBuf::from(dest_url),
+    };
+    match base {
+        Some(base) => Some(base.join(path)),
+        None => Some(path),
+    }
+}
+
+/// Opens the image, from its file or from the data it is embedded with. SVG images are recognised
+/// by the extension of their file, or by their media type.
+pub fn open(dest_url: &str, base: Option<&Path>) -> ImageResult<Image> {
+    let Some(data) = dest_url.strip_prefix("data:") else {
+        let path = path(dest_url, base).unwrap();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        return match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("svg" | "svgz") => svg(&std::fs::read(&path)?, path.parent()),
+            _ => image::open(path).map(Image::Raster),
+        };
+    };
+    let (mime, bytes) = decode_data(data).ok_or_else(|| {
+        ImageError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the data URI is not valid",
+        ))
+    })?;
+    if mime == "image/svg+xml" {
+        return svg(&bytes, base);
+    }
+    match ImageFormat::from_mime_type(mime) {
+        Some(format) => image::load_from_memory_with_format(&bytes, format),
+        None => image::load_from_memory(&bytes),
+    }
+    .map(Image::Raster)
+}
+
+/// Parses an SVG image. The files it refers to are relative to `resources`.
+fn svg(data: &[u8], resources: Option<&Path>) -> ImageResult<Image> {
+    let options = usvg::Options {
+        resources_dir: resources.map(Path::to_path_buf),
+        fontdb: fonts(),
+        ..usvg::Options::default()
+    };
+    usvg::Tree::from_data(data, &options)
+        .map(Box::new)
+        .map(Image::Vector)
+        .map_err(|error| {
+            ImageError::Decoding(DecodingError::new(
+                ImageFormatHint::Name("SVG".to_owned()),
+                error,
+            ))
+        })
+}
+
+/// The fonts that text in SVG images may be drawn with: those installed, as well as the font the
+/// paper is printed with in PDFs, so that there is always one to fall back on. Finding the installed
+/// fonts takes a while, so it is only done once, and only when there is an SVG image.
+fn fonts() -> Arc<fontdb::Database> {
+    static FONTS: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+    FONTS
+        .get_or_init(|| {
+            let mut fonts = fontdb::Database::new();
+            fonts.load_system_fonts();
+            fonts.load_font_data(include_bytes!("fonts/DejaVuSansMono.ttf").to_vec());
+            // Text in a generic family, like sans-serif, is left out when none of its fonts are
+            // installed, so each is given the first of the usual fonts that is.
+            let installed = |fonts: &fontdb::Database, family: &str| {
+                fonts
+                    .faces()
+                    .any(|face| face.families.iter().any(|(name, _)| name == family))
+            };
+            let serif = [
+                "Times New Roman",
+                "DejaVu Serif",
+                "Liberation Serif",
+                "Noto Serif",
+            ];
+            let sans_serif = [
+                "Arial",
+                "Helvetica",
+                "DejaVu Sans",
+                "Liberation Sans",
+                "Noto Sans",
+            ];
+            let monospace = ["Courier New", "DejaVu Sans Mono", "Liberation Mono"];
+            let first = |fonts: &fontdb::Database, families: &[&str]| {
+                families
+                    .iter()
+                    .copied()
+                    .find(|family| installed(fonts, family))
+                    .unwrap_or("DejaVu Sans Mono")
+                    .to_owned()
+            };
+            let (serif, sans_serif, monospace) = (
+                first(&fonts, &serif),
+                first(&fonts, &sans_serif),
+                first(&fonts, &monospace),
+            );
+            fonts.set_serif_family(serif);
+            fonts.set_sans_serif_family(sans_serif);
+            fonts.set_monospace_family(monospace);
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+/// Draws the SVG image, stretched to the size.
+fn draw(tree: &usvg::Tree, width: u32, height: u32) -> DynamicImage {
+    let (width, height) = (width.max(1), height.max(1));
+    let mut pixmap = Pixmap::new(width, height).unwrap();
+    let size = tree.size();
+    let transform =
+        Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let colour = pixel.demultiply();
+            [colour.red(), colour.green(), colour.blue(), colour.alpha()]
+        })
+        .collect();
+    DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, pixels).unwrap())
+}
+
+/// Splits the data of a `data:` URI into its media type and its bytes, which are either in base64
+/// or percent encoded.
+fn decode_data(data: &str) -> Option<(&str, Vec<u8>)> {
+    let (header, payload) = data.split_once(',')?;
+    match header.strip_suffix(";base64") {
+        Some(header) => {
+            let payload = payload
+                .chars()
+                .filter(|ch| !ch.is_ascii_whitespace())
+                .collect::<String>();
+            let bytes = BASE64.decode(percent_decode(&payload)).ok()?;
+            Some((media_type(header), bytes))
+        }
+        None => Some((media_type(header), percent_decode(payload))),
+    }
+}
+
+/// The media type of a `data:` URI, without its parameters.
+fn media_type(header: &str) -> &str {
+    header.split(';').next().unwrap_or_default()
+}
+
+/// Replaces each `%` escape with the byte it stands for.
+fn percent_decode(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(escaped) if byte == b'%' => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    bytes
+}
@@ -0,0 +1,246 @@
+//! The intermediate representation between parsing a document and writing it out: a list of lines,
+//! each made of styled spans of text.
+
+use crate::str_width;
+use ansi_term::Style;
+use image::DynamicImage;
+use std::sync::Arc;
+
+/// What part of the paper a span of text makes up.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    /// Blank space around the content: the centering, the paper's margins, and its shadow.
+    Margin,
+    /// Decorations drawn around the content, such as list bullets and blockquote markers.
+    Prefix,
+    /// The text of the document itself.
+    Content,
+    /// Two pixels of an image, drawn as a lower half block: the background is the top pixel, and
+    /// the foreground is the bottom pixel.
+    Image,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+    /// Byte offset of the Markdown source this span was rendered from, if any.
+    pub offset: Option<usize>,
+    pub kind: SpanKind,
+    /// The image that a row of pixels belongs to, on the first span of the row. Outputs that can
+    /// draw images use this to draw the image itself in place of its pixels.
+    pub picture: Option<Picture>,
+}
+
+/// A row of an image that has been drawn as pixels.
+#[derive(Clone, Debug)]
+pub struct Picture {
+    pub image: Arc<DynamicImage>,
+    /// Which row of pixels this is.
+    pub row: usize,
+    /// The number of rows of pixels that the whole image takes.
+    pub rows: usize,
+}
+
+impl PartialEq for Picture {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.image, &other.image) && self.row == other.row && self.rows == other.rows
+    }
+}
+
+impl Span {
+    pub fn new(text: impl Into<String>, style: Style, kind: SpanKind) -> Self {
+        Self {
+            text: text.into(),
+            style,
+            offset: None,
+            kind,
+            picture: None,
+        }
+    }
+
+    pub fn margin(text: impl Into<String>, style: Style) -> Self {
+        Self::new(text, style, SpanKind::Margin)
+    }
+
+    pub fn prefix(text: impl Into<String>, style: Style) -> Self {
+        Self::new(text, style, SpanKind::Prefix)
+    }
+
+    pub fn content(text: impl Into<String>, style: Style) -> Self {
+        Self::new(text, style, SpanKind::Content)
+    }
+
+    pub fn at(mut self, offset: Option<usize>) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        str_width(&self.text)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    pub spans: Vec<Span>,
+}
+
+impl Line {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, span: Span) {
+        if !span.text.is_empty() {
+            self.spans.push(span);
+        }
+    }
+
+    pub fn extend(&mut self, spans: impl IntoIterator<Item = Span>) {
+        for span in spans {
+            self.push(span);
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        width(&self.spans)
+    }
+
+    /// The text of the line, without any styling.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+}
+
+impl FromIterator<Span> for Line {
+    fn from_iter<I: IntoIterator<Item = Span>>(iter: I) -> Self {
+        let mut line = Line::new();
+        line.extend(iter);
+        line
+    }
+}
+
+pub fn width(spans: &[Span]) -> usize {
+    spans.iter().map(Span::width).sum()
+}
+
+/// Splits the spans into lines no wider than `width`, breaking wherever the width runs out.
+pub fn wrap(spans: Vec<Span>, width: usize) -> Vec<Vec<Span>> {
+    let mut lines = vec![vec![]];
+    let mut used = 0;
+    for span in spans {
+        let mut text = String::new();
+        for ch in span.text.chars() {
+            let ch_width = str_width(&ch.to_string());
+            if used + ch_width > width && used > 0 {
+                if !text.is_empty() {
+                    lines.last_mut().unwrap().push(Span {
+                        text: std::mem::take(&mut text),
+                        ..span.clone()
+                    });
+                }
+                lines.push(vec![]);
+                used = 0;
+            }
+            text.push(ch);
+            used += ch_width;
+        }
+        if !text.is_empty() {
+            lines.last_mut().unwrap().push(Span { text, ..span });
+        }
+    }
+    lines
+}
+
+/// Where an image is drawn among some lines. The image may be cut off, such as by the edge of a
+/// sheet, so only some of its rows are visible.
+pub(crate) struct Placement {
+    pub image: Arc<DynamicImage>,
+    pub column: usize,
+    pub columns: usize,
+    /// The line the visible rows start on.
+    pub line: usize,
+    /// The visible rows of the image.
+    pub first_row: usize,
+    pub last_row: usize,
+    pub rows: usize,
+}
+
+impl Placement {
+    pub fn visible_rows(&self) -> usize {
+        self.last_row - self.first_row + 1
+    }
+}
+
+/// Finds the images among the lines, from the pictures on their rows of pixels.
+pub(crate) fn placements(lines: &[Line]) -> Vec<Placement> {
+    let mut placements: Vec<Placement> = vec![];
+    for (line_index, line) in lines.iter().enumerate() {
+        let mut column = 0;
+        let mut spans = line.spans.iter().peekable();
+        while let Some(span) = spans.next() {
+            let start = column;
+            column += span.width();
+            let Some(picture) = &span.picture else {
+                continue;
+            };
+            while let Some(span) =
+                spans.next_if(|span| span.kind == SpanKind::Image && span.picture.is_none())
+            {
+                column += span.width();
+            }
+            let continues = placements.iter_mut().find(|placement| {
+                Arc::ptr_eq(&placement.image, &picture.image)
+                    && placement.column == start
+                    && placement.last_row + 1 == picture.row
+                    && placement.line + placement.visible_rows() == line_index
+            });
+            match continues {
+                Some(placement) => placement.last_row = picture.row,
+                None => placements.push(Placement {
+                    image: picture.image.clone(),
+                    column: start,
+                    columns: column - start,
+                    line: line_index,
+                    first_row: picture.row,
+                    last_row: picture.row,
+                    rows: picture.rows,
+                }),
+            }
+        }
+    }
+    placements
+}
+
+/// Applies the attributes that are set in `top` over those of `base`.
+pub fn overlay(base: Style, top: Style) -> Style {
+    Style {
+        foreground: top.foreground.or(base.foreground),
+        background: top.background.or(base.background),
+        is_bold: base.is_bold || top.is_bold,
+        is_dimmed: base.is_dimmed || top.is_dimmed,
+        is_italic: base.is_italic || top.is_italic,
+        is_underline: base.is_underline || top.is_underline,
+        is_blink: base.is_blink || top.is_blink,
+        is_reverse: base.is_reverse || top.is_reverse,
+        is_hidden: base.is_hidden || top.is_hidden,
+        is_strikethrough: base.is_strikethrough || top.is_strikethrough,
+    }
+}
+
+/// A heading in the document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Heading {
+    pub level: u8,
+    pub text: String,
+    /// The index of the line of the body on which the heading starts.
+    pub line: usize,
+}
+
+/// The laid out body of a document, before it has been put onto paper.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Body {
+    pub lines: Vec<Line>,
+    pub headings: Vec<Heading>,
+}
@@ -0,0 +1,656 @@
+use ansi_term::Style;
+use console::strip_ansi_codes;
+use image::imageops::FilterType;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::num::ParseIntError;
+use std::path::PathBuf;
+use std::str::FromStr;
+use syncat_stylesheet::Stylesheet;
+
+pub mod ansi;
+mod cache;
+mod dirs;
+mod filter;
+mod graphics;
+#[cfg(feature = "highlight")]
+mod highlight;
+pub mod html;
+mod images;
+pub mod layout;
+mod numbering;
+pub mod pager;
+mod palette;
+pub mod pdf;
+mod printer;
+mod running;
+mod sheet;
+mod str_width;
+pub mod svg;
+mod table;
+mod termpix;
+mod toc;
+pub mod watch;
+mod words;
+
+use cache::Cache;
+use filter::Outputs;
+pub use graphics::{DEFAULT_CELL_SIZE, ImageProtocol, terminal_cell_size};
+use layout::Body;
+pub use layout::{Line, Picture, Span, SpanKind};
+pub use palette::ColorDepth;
+use printer::Printer;
+pub use running::MarginText;
+use running::Placeholders;
+use sheet::{Page, Sheet};
+use str_width::str_width;
+pub use termpix::Dither;
+use words::Words;
+
+/// Where to position the paper within the terminal.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Position {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+/// A length in cells, or as a percentage of the space there is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Extent {
+    Cells(usize),
+    Percent(usize),
+}
+
+impl Extent {
+    /// The length, in cells, out of the space there is.
+    pub fn of(self, space: usize) -> usize {
+        match self {
+            Self::Cells(cells) => cells,
+            Self::Percent(percent) => space * percent / 100,
+        }
+    }
+}
+
+/// Parses an extent as a number of cells, like `40`, or a percentage, like `50%`.
+impl FromStr for Extent {
+    type Err = ParseIntError;
+
+    fn from_str(extent: &str) -> Result<Self, Self::Err> {
+        match extent.strip_suffix('%') {
+            Some(percent) => percent.parse().map(Self::Percent),
+            None => extent.parse().map(Self::Cells),
+        }
+    }
+}
+
+/// Options controlling how a document is rendered onto the paper.
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// Horizontal margin
+    pub h_margin: usize,
+    /// Vertical margin
+    pub v_margin: usize,
+    /// The width of the paper (including the space used for the margin)
+    pub width: usize,
+    /// The width of the terminal the paper is positioned within
+    pub terminal_width: usize,
+    /// Where to position the paper within the terminal
+    pub position: Position,
+    /// The number of columns to lay the body out in. Each column is as wide as the body of a
+    /// single column paper, and the paper widens to fit them, as far as the terminal allows.
+    pub columns: usize,
+    /// The space between columns
+    pub gutter: usize,
+    /// The height of each sheet of paper. The document is split across as many sheets as it takes
+    /// to fit, each of which is numbered. When `None`, the whole document is put on one sheet.
+    pub page_height: Option<usize>,
+    /// Text to write in the top margin of each sheet. Without a vertical margin, a line is added to
+    /// write it on.
+    pub header: MarginText,
+    /// Text to write in the bottom margin of each sheet. When paginated and no footer is given, the
+    /// page number is written instead. Without a vertical margin, a line is added to write it on.
+    pub footer: MarginText,
+    /// The file the document was read from, if any
+    pub path: Option<PathBuf>,
+    /// Print a table of contents, at the `[TOC]` placeholder or at the top of the document. When
+    /// paginated, each entry includes the number of the page the heading is on.
+    pub toc: bool,
+    /// Number the headings, like `1.`, `1.2.` and `1.2.3.`, starting from headings of this level.
+    /// Headings above this level are not numbered.
+    pub number_headings: Option<u8>,
+    /// Don't parse as Markdown, just render the plain text on a paper
+    pub plain: bool,
+    /// The length to consider tabs as
+    pub tab_length: usize,
+    /// Hide link URLs
+    pub hide_urls: bool,
+    /// Disable drawing images
+    pub no_images: bool,
+    /// How to draw images. With a graphics protocol, images are sized by the cells of the terminal,
+    /// rather than taking up a cell for each pixel.
+    pub image_protocol: ImageProtocol,
+    /// The size of each cell of the terminal, in pixels, which images are shaped to fit, and drawn
+    /// at the resolution of. Papers look the same wherever they are rendered unless this is given.
+    pub cell_size: (u32, u32),
+    /// How to round the colours of images drawn as half blocks to the colours the terminal can
+    /// show. Makes no difference with true colour.
+    pub dither: Dither,
+    /// The filter used to resample images to the size they are drawn at
+    pub image_filter: FilterType,
+    /// The widest that images are drawn, out of the width of the body of the paper. Images are
+    /// never drawn wider than the body.
+    pub image_width: Option<Extent>,
+    /// The most lines that an image may take up
+    pub image_max_height: Option<usize>,
+    /// Use syncat to highlight code blocks. Requires you have syncat installed.
+    pub syncat: bool,
+    /// Highlight code blocks with the grammars built into the binary. Requires the `highlight`
+    /// feature. When `syncat` is also set, this is used for the code blocks syncat fails on.
+    pub highlight: bool,
+    /// Commands to filter code blocks through, by the first word of the code block's info string.
+    /// These take precedence over syncat and the built-in highlighting.
+    pub filters: HashMap<String, Filter>,
+    /// Keep the output of syncat and filters in the user's cache directory, to be reused when the
+    /// same code block is printed again at the same width and with the same stylesheet.
+    pub cache: bool,
+    /// Style the paper with ANSI escape sequences. Without them, the layout of the paper is still
+    /// drawn, but images are left out, as they are nothing but colour.
+    pub color: bool,
+    /// How many colours the terminal can show. The colours of the stylesheet and of images are
+    /// replaced by the closest that it can.
+    pub color_depth: ColorDepth,
+    /// The stylesheet used to style the paper
+    pub stylesheet: Stylesheet,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            h_margin: 6,
+            v_margin: 6,
+            width: 92,
+            terminal_width: 93,
+            position: Position::default(),
+            columns: 1,
+            gutter: 4,
+            page_height: None,
+            header: MarginText::default(),
+            footer: MarginText::default(),
+            path: None,
+            toc: false,
+            number_headings: None,
+            plain: false,
+            tab_length: 4,
+            hide_urls: false,
+            no_images: false,
+            image_protocol: ImageProtocol::Halfblock,
+            cell_size: DEFAULT_CELL_SIZE,
+            dither: Dither::None,
+            image_filter: FilterType::Nearest,
+            image_width: None,
+            image_max_height: None,
+            syncat: false,
+            highlight: false,
+            filters: HashMap::new(),
+            cache: false,
+            color: true,
+            color_depth: ColorDepth::TrueColor,
+            stylesheet: default_stylesheet(),
+        }
+    }
+}
+
+/// A command that code blocks are filtered through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    /// The command line, which is run by the shell with the code on its standard input and
+    /// `COLUMNS` set to the width available to the code.
+    pub command: String,
+    /// Print the output in place of the whole code block, rather than within its frame.
+    pub replace: bool,
+}
+
+/// The stylesheet that is used when the user has not provided one.
+pub fn default_stylesheet() -> Stylesheet {
+    include_str!("default.syncat")
+        .parse::<Stylesheet>()
+        .unwrap()
+}
+
+/// Loads `paper.syncat` from the active syncat theme, falling back to the default stylesheet.
+pub fn active_stylesheet() -> Stylesheet {
+    Stylesheet::from_file(dirs::active_color().join("paper.syncat"))
+        .unwrap_or_else(|_| default_stylesheet())
+}
+
+/// Loads the code block filters from `config.toml` in paper's config directory, where the
+/// `[filter]` and `[filter-replace]` tables map languages to commands. It is not an error for the
+/// file to be missing.
+pub fn configured_filters() -> io::Result<HashMap<String, Filter>> {
+    let path = dirs::paper_config().join("config.toml");
+    let config = match std::fs::read_to_string(&path) {
+        Ok(config) => config,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(error) => return Err(error),
+    };
+    let invalid = |message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), message),
+        )
+    };
+    let config = config
+        .parse::<toml::Table>()
+        .map_err(|error| invalid(error.message().to_owned()))?;
+    let mut filters = HashMap::new();
+    for (name, replace) in [("filter", false), ("filter-replace", true)] {
+        let Some(table) = config.get(name) else {
+            continue;
+        };
+        let table = table
+            .as_table()
+            .ok_or_else(|| invalid(format!("`{}` should be a table", name)))?;
+        for (lang, command) in table {
+            let command = command
+                .as_str()
+                .ok_or_else(|| invalid(format!("the filter for `{}` should be a string", lang)))?;
+            filters.insert(
+                lang.to_owned(),
+                Filter {
+                    command: command.to_owned(),
+                    replace,
+                },
+            );
+        }
+    }
+    Ok(filters)
+}
+
+/// Removes the output of syncat and filters from the user's cache directory.
+pub fn clear_cache() -> io::Result<()> {
+    cache::clear()
+}
+
+fn normalize(tab_len: usize, source: &str) -> String {
+    source
+        .lines()
+        .map(|line| {
+            let mut len = 0;
+            let line = strip_ansi_codes(line);
+            if line.contains('\t') {
+                line.chars()
+                    .flat_map(|ch| {
+                        if ch == '\t' {
+                            let missing = tab_len - (len % tab_len);
+                            len += missing;
+                            vec![' '; missing]
+                        } else {
+                            len += 1;
+                            vec![ch]
+                        }
+                    })
+                    .collect::<String>()
+                    .into()
+            } else {
+                line
+            }
+        })
+        .map(|line| format!("{}\n", line))
+        .collect::<String>()
+}
+
+/// Renders a document onto a paper, writing the result to `out`.
+pub fn render(markdown: &str, opts: &RenderOptions, out: &mut impl Write) -> io::Result<()> {
+    let lines = render_lines(markdown, opts)?;
+    ansi::write_lines_with_images(
+        &lines,
+        opts.image_protocol,
+        opts.cell_size,
+        opts.color_depth,
+        out,
+    )
+}
+
+/// Renders a document onto a paper, returning the laid out lines.
+pub fn render_lines(markdown: &str, opts: &RenderOptions) -> io::Result<Vec<Line>> {
+    let mut lines = render_paper(markdown, opts)?;
+    place(&mut lines, opts);
+    Ok(lines)
+}
+
+/// The space between papers placed side by side.
+const SIDE_BY_SIDE_GAP: usize = 2;
+
+/// The width of the terminal it takes to fit this many papers side by side at their full width,
+/// shadows included.
+pub fn side_by_side_width(papers: usize, opts: &RenderOptions) -> usize {
+    papers * (paper_width(opts) + 1) + papers.saturating_sub(1) * SIDE_BY_SIDE_GAP
+}
+
+/// Renders several documents onto papers placed side by side, returning the laid out lines. Each
+/// paper gets an equal share of the terminal, and the papers' lines are lined up with each other,
+/// so that the same part of each document appears at the same height when they are paginated the
+/// same way. The terminal width and position are taken from the first document's options.
+pub fn render_side_by_side(documents: &[(&str, RenderOptions)]) -> io::Result<Vec<Line>> {
+    let Some((_, first)) = documents.first() else {
+        return Ok(vec![]);
+    };
+    let share = first
+        .terminal_width
+        .saturating_sub((documents.len() - 1) * SIDE_BY_SIDE_GAP)
+        / documents.len();
+    let papers = documents
+        .iter()
+        .map(|(markdown, opts)| {
+            render_paper(
+                markdown,
+                &RenderOptions {
+                    terminal_width: share,
+                    ..opts.clone()
+                },
+            )
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let widths = papers
+        .iter()
+        .map(|paper| paper.iter().map(Line::width).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let height = papers.iter().map(Vec::len).max().unwrap_or(0);
+    let mut papers = papers
+        .into_iter()
+        .map(|paper| paper.into_iter())
+        .collect::<Vec<_>>();
+    let mut lines = (0..height)
+        .map(|_| {
+            let mut line = Line::new();
+            for (index, (paper, width)) in papers.iter_mut().zip(&widths).enumerate() {
+                if index > 0 {
+                    line.push(Span::margin(" ".repeat(SIDE_BY_SIDE_GAP), Style::default()));
+                }
+                let paper_line = paper.next().unwrap_or_default();
+                let padding = width - paper_line.width();
+                line.extend(paper_line.spans);
+                line.push(Span::margin(" ".repeat(padding), Style::default()));
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+    place(&mut lines, first);
+    Ok(lines)
+}
+
+/// Moves the lines over to the requested position within the terminal. The last column of the
+/// lines is the paper's shadow, which may hang off the edge.
+fn place(lines: &mut [Line], opts: &RenderOptions) {
+    let width = lines.iter().map(Line::width).max().unwrap_or(0);
+    let width = width.saturating_sub(1);
+    let left_space = match opts.position {
+        Position::Left => 0,
+        Position::Right => opts.terminal_width.saturating_sub(width + 1),
+        Position::Center => opts.terminal_width.saturating_sub(width) / 2,
+    };
+    if left_space > 0 {
+        for line in lines {
+            line.spans
+                .insert(0, Span::margin(" ".repeat(left_space), Style::default()));
+        }
+    }
+}
+
+/// Renders a document onto sheets of paper, returning the laid out lines of each sheet separately.
+/// Each sheet casts a shadow one column to its right and one line below it, which is included in
+/// its lines.
+pub fn render_sheets(markdown: &str, opts: &RenderOptions) -> io::Result<Vec<Vec<Line>>> {
+    let width = usize::min(paper_width(opts), opts.terminal_width.saturating_sub(1));
+    if width < opts.h_margin * 2 + 40 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The width is too short!",
+        ));
+    }
+    if opts.columns > 1 && printer::column_width(width - 2 * opts.h_margin, opts) < 20 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The columns are too narrow!",
+        ));
+    }
+
+    // The running header and footer are written in the margins, so there must be at least a line
+    // of margin to write them in.
+    let has_footer = !opts.footer.is_empty() || opts.page_height.is_some();
+    let top_margin = opts.v_margin.max(usize::from(!opts.header.is_empty()));
+    let bottom_margin = opts.v_margin.max(usize::from(has_footer));
+    let sheet = Sheet::new(
+        &opts.stylesheet,
+        width,
+        opts.h_margin,
+        top_margin,
+        bottom_margin,
+    );
+    if let Some(page_height) = opts.page_height
+        && sheet.body_height(page_height).is_none()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The page height is too short!",
+        ));
+    }
+
+    let paper_style = printer::resolve_scopes(&opts.stylesheet, &["paper"], None);
+    let available_width = width - 2 * opts.h_margin;
+    let body_height = opts
+        .page_height
+        .and_then(|height| sheet.body_height(height));
+
+    let source = normalize(opts.tab_length, markdown);
+    let mut outputs = if opts.cache {
+        Outputs::cached(Cache::new(&opts.stylesheet))
+    } else {
+        Outputs::default()
+    };
+    if (opts.syncat || !opts.filters.is_empty()) && !opts.plain {
+        // Find all the code blocks first, so they can be highlighted at the same time.
+        outputs.prefetch(printer::command_blocks(&source, available_width, opts));
+    }
+    let body = if opts.plain {
+        plain_body(&source, available_width, paper_style)
+    } else if opts.toc {
+        let mut entries = toc::entries(&source, opts.number_headings);
+        let body = print_body(
+            &source,
+            available_width,
+            body_height,
+            opts,
+            Some(&entries),
+            &outputs,
+        );
+        match body_height {
+            // The table of contents takes up the same number of lines with or without the page
+            // numbers, so the headings stay on the same pages when it is printed again with them.
+            Some(lines_per_page) => {
+                for (entry, heading) in entries.iter_mut().zip(&body.headings) {
+                    entry.page = Some(heading.line / lines_per_page + 1);
+                }
+                print_body(
+                    &source,
+                    available_width,
+                    body_height,
+                    opts,
+                    Some(&entries),
+                    &outputs,
+                )
+            }
+            None => body,
+        }
+    } else {
+        print_body(&source, available_width, body_height, opts, None, &outputs)
+    };
+
+    let pages = pages(&sheet, body, opts);
+    let mut sheets = sheet.frame(pages);
+    if !opts.color || opts.color_depth != ColorDepth::TrueColor {
+        for span in sheets.iter_mut().flatten().flat_map(|line| &mut line.spans) {
+            span.style = if opts.color {
+                opts.color_depth.style(span.style)
+            } else {
+                Style::default()
+            };
+        }
+    }
+    Ok(sheets)
+}
+
+/// Renders a document onto a paper, at the left edge of the terminal.
+fn render_paper(markdown: &str, opts: &RenderOptions) -> io::Result<Vec<Line>> {
+    Ok(render_sheets(markdown, opts)?.concat())
+}
+
+/// Prints the Markdown document into the body of the paper. The table of contents, if any, goes in
+/// place of the `[TOC]` placeholder, or at the top if there isn't one.
+fn print_body(
+    source: &str,
+    available_width: usize,
+    body_height: Option<usize>,
+    opts: &RenderOptions,
+    toc: Option<&[toc::Entry]>,
+    outputs: &Outputs,
+) -> Body {
+    let mut printer = Printer::new(available_width, body_height, opts, outputs);
+    if let Some(entries) = toc
+        && !toc::has_placeholder(source)
+    {
+        printer.print_toc(entries);
+    }
+    let mut in_placeholder = false;
+    for (event, range) in Parser::new_ext(source, Options::all()).into_offset_iter() {
+        if let Some(entries) = toc {
+            match event {
+                Event::Start(Tag::Paragraph) if toc::is_placeholder(&source[range.clone()]) => {
+                    printer.print_toc(entries);
+                    in_placeholder = true;
+                    continue;
+                }
+                Event::End(TagEnd::Paragraph) if in_placeholder => {
+                    in_placeholder = false;
+                    continue;
+                }
+                _ if in_placeholder => continue,
+                _ => {}
+            }
+        }
+        printer.handle(event, range.start);
+    }
+    printer.finish()
+}
+
+/// The width of the paper needed to fit all the columns.
+fn paper_width(opts: &RenderOptions) -> usize {
+    if opts.columns <= 1 {
+        return opts.width;
+    }
+    let column_width = opts.width.saturating_sub(2 * opts.h_margin);
+    2 * opts.h_margin + opts.columns * column_width + (opts.columns - 1) * opts.gutter
+}
+
+/// Splits the body into pages, and fills in the running header and footer of each.
+fn pages(sheet: &Sheet, body: Body, opts: &RenderOptions) -> Vec<Page> {
+    let pages = sheet.paginate(body.lines, opts.page_height);
+    let footer = if opts.footer.is_empty() && opts.page_height.is_some() {
+        MarginText {
+            center: "Page {page} of {pages}".to_owned(),
+            ..MarginText::default()
+        }
+    } else {
+        opts.footer.clone()
+    };
+    let footer_scopes: &[&str] = if opts.footer.is_empty() {
+        &["paper", "page-number"]
+    } else {
+        &["paper", "footer"]
+    };
+    let header_style = printer::resolve_scopes(&opts.stylesheet, &["paper", "header"], None);
+    let footer_style = printer::resolve_scopes(&opts.stylesheet, footer_scopes, None);
+
+    let file = opts
+        .path
+        .as_ref()
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let date = running::today();
+    let total = pages.len();
+    let mut end = 0;
+    pages
+        .into_iter()
+        .enumerate()
+        .map(|(index, body_lines)| {
+            end += body_lines.len();
+            let placeholders = Placeholders {
+                file: &file,
+                heading: running::current_heading(&body.headings, end),
+                page: index + 1,
+                pages: total,
+                date: &date,
+            };
+            let margin_line = |text: &MarginText, style| {
+                (!text.is_empty()).then(|| sheet.margin_line(&placeholders.expand(text), style))
+            };
+            Page {
+                body: body_lines,
+                header: margin_line(&opts.header, header_style),
+                footer: margin_line(&footer, footer_style),
+            }
+        })
+        .collect()
+}
+
+fn plain_body(source: &str, available_width: usize, paper_style: Style) -> Body {
+    let mut lines = vec![];
+    let mut push_line = |buffer: &str| {
+        lines.push(Line::from_iter([
+            Span::content(buffer, paper_style),
+            Span::margin(
+                " ".repeat(available_width.saturating_sub(str_width(buffer))),
+                paper_style,
+            ),
+        ]));
+    };
+    for line in source.lines() {
+        let mut buffer = String::new();
+        let mut indent = None;
+        for word in Words::preserving_whitespace(line) {
+            if str_width(&buffer) + str_width(&word) > available_width {
+                push_line(&buffer);
+                buffer.clear();
+            }
+            if buffer.is_empty() {
+                let indent = indent.get_or_insert_with(|| {
+                    let indent_len = word.chars().take_while(|ch| ch.is_whitespace()).count();
+                    word[0..indent_len].to_string()
+                });
+                buffer.push_str(indent);
+                buffer.push_str(word.trim());
+            } else {
+                buffer.push_str(&word);
+            }
+        }
+        push_line(&buffer);
+    }
+    Body {
+        lines,
+        headings: vec![],
+    }
+}
+
+/// Prints the events produced by parsing a document, for debugging purposes.
+pub fn render_events(markdown: &str, opts: &RenderOptions, out: &mut impl Write) -> io::Result<()> {
+    let source = normalize(opts.tab_length, markdown);
+    for event in Parser::new_ext(&source, Options::all()) {
+        writeln!(out, "{:?}", event)?;
+    }
+    Ok(())
+}
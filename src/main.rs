@@ -1,25 +1,20 @@
 use ansi_term::Style;
+use clap::error::ErrorKind;
 use clap::{CommandFactory, Parser as _};
 use clap_complete::Shell;
-use console::strip_ansi_codes;
-use pulldown_cmark::{Options, Parser};
-use std::convert::TryInto;
-use std::fs;
-use std::io::{self, Read};
+use crossterm::{cursor, execute, terminal};
+use image::imageops::FilterType;
+use paper_terminal::watch::{self, Watcher};
+use paper_terminal::{
+    ColorDepth, DEFAULT_CELL_SIZE, Dither, Extent, Filter, ImageProtocol, Line, MarginText,
+    Position, RenderOptions, Span, ansi, html, pager, pdf, svg,
+};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal as _, Read, Write};
 use std::path::PathBuf;
-use syncat_stylesheet::Stylesheet;
-use terminal_size::{Width, terminal_size};
-
-mod dirs;
-mod printer;
-mod str_width;
-mod table;
-mod termpix;
-mod words;
-
-use printer::Printer;
-use str_width::str_width;
-use words::Words;
+use std::thread;
+use terminal_size::{Height, Width, terminal_size};
 
 /// Prints papers in your terminal
 #[derive(clap::Parser, Debug)]
@@ -42,6 +37,47 @@ pub struct Opts {
     #[structopt(short = 'w', long, default_value = "92")]
     pub width: usize,
 
+    /// Lay the document out in this many columns, widening the paper to fit them. First level
+    /// headings span all the columns
+    #[structopt(long, default_value = "1", value_name = "N")]
+    pub columns: usize,
+
+    /// The space between columns
+    #[structopt(long, default_value = "4")]
+    pub gutter: usize,
+
+    /// Split the document across sheets of paper of this height (including the margins), with a
+    /// page number in each bottom margin (unless --footer is given)
+    #[structopt(long)]
+    pub page_height: Option<usize>,
+
+    /// Text to write in the top margin of each sheet, as `left|center|right`. May include the
+    /// placeholders {file}, {heading}, {page}, {pages}, and {date}
+    #[structopt(long, value_name = "TEMPLATE")]
+    pub header: Option<MarginText>,
+
+    /// Text to write in the bottom margin of each sheet, as `left|center|right`. May include the
+    /// same placeholders as --header
+    #[structopt(long, value_name = "TEMPLATE")]
+    pub footer: Option<MarginText>,
+
+    /// Print a table of contents at the top of the document, or in place of a `[TOC]` paragraph.
+    /// When paginated, the page numbers of the headings are included
+    #[structopt(long)]
+    pub toc: bool,
+
+    /// Number the headings, like 1., 1.2. and 1.2.3., starting from headings of this level, so that
+    /// a document's title can be left unnumbered with --number-headings=2
+    #[structopt(
+        long,
+        value_name = "LEVEL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1",
+        value_parser = clap::value_parser!(u8).range(1..=6)
+    )]
+    pub number_headings: Option<u8>,
+
     /// Don't parse as Markdown, just render the plain text on a paper
     #[structopt(short = 'p', long)]
     pub plain: bool,
@@ -58,6 +94,33 @@ pub struct Opts {
     #[structopt(short = 'I', long)]
     pub no_images: bool,
 
+    /// How to draw images: as half blocks, which work in any terminal, or with a terminal graphics
+    /// protocol. With auto, the protocol is guessed from the environment when writing to a
+    /// terminal. The pager always uses half blocks
+    #[structopt(long, value_enum, default_value_t, value_name = "PROTOCOL")]
+    pub image_protocol: ImageProtocolChoice,
+
+    /// How to round the colours of images to those the terminal can show, when it can't show them
+    /// all: floyd-steinberg spreads the difference onto the pixels around, and bayer nudges each
+    /// pixel by a pattern
+    #[structopt(long, value_enum, default_value_t, value_name = "METHOD")]
+    pub dither: DitherChoice,
+
+    /// How to resample images to the size they are drawn at. Filters other than nearest blend
+    /// pixels together, which keeps thin lines visible when images are shrunk
+    #[structopt(long, value_enum, default_value_t, value_name = "FILTER")]
+    pub image_filter: ImageFilterChoice,
+
+    /// The widest to draw images, in columns, or as a percentage of the width of the paper's body,
+    /// like 50%
+    #[structopt(long, value_name = "WIDTH")]
+    pub image_width: Option<Extent>,
+
+    /// The tallest to draw images, in lines, or as a percentage of the height of the terminal, like
+    /// 80%. Images keep their shape, so are drawn narrower to fit
+    #[structopt(long, value_name = "HEIGHT")]
+    pub image_max_height: Option<Extent>,
+
     /// Position paper on the left edge of the terminal, instead of centred.
     #[structopt(short = 'l', long)]
     pub left: bool,
@@ -70,6 +133,66 @@ pub struct Opts {
     #[structopt(short, long)]
     pub syncat: bool,
 
+    /// Highlight code blocks using the grammars built into paper, without needing syncat.
+    #[cfg(feature = "highlight")]
+    #[structopt(short = 'H', long)]
+    pub highlight: bool,
+
+    /// Filter the code blocks of a language through a command, which is run by the shell, printing
+    /// its output within the code block. May be given more than once. Overrides the [filter] table
+    /// of the config file
+    #[structopt(long, value_name = "LANG=COMMAND", value_parser = parse_filter)]
+    pub filter: Vec<(String, String)>,
+
+    /// Filter the code blocks of a language through a command, printing its output in place of the
+    /// whole code block. Overrides the [filter-replace] table of the config file
+    #[structopt(long, value_name = "LANG=COMMAND", value_parser = parse_filter)]
+    pub filter_replace: Vec<(String, String)>,
+
+    /// Run syncat and filters on every code block, rather than reusing their output from the cache
+    #[structopt(long)]
+    pub no_cache: bool,
+
+    /// Remove the output of syncat and filters from the cache, then exit
+    #[structopt(long)]
+    pub clear_cache: bool,
+
+    /// Print the files on papers placed side by side, instead of one after another. Not available
+    /// for PDF, where each paper has pages of its own
+    #[structopt(long)]
+    pub side_by_side: bool,
+
+    /// Print the files again whenever they, or the images they refer to, change. Ignored when
+    /// reading from standard input
+    #[structopt(long)]
+    pub watch: bool,
+
+    /// View the paper in an interactive pager. Ignored when not writing to a terminal.
+    #[structopt(long)]
+    pub pager: bool,
+
+    /// The format to print the paper in. HTML is a standalone page, with images embedded, SVG is a
+    /// vector screenshot, and PDF has a page for each sheet of paper. Papers printed in other formats
+    /// than for the terminal ignore --pager and --watch
+    #[structopt(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub output_format: OutputFormat,
+
+    /// Write the paper to this file, instead of to standard output
+    #[structopt(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// When to style the paper with colours. Without them, the paper's layout is still drawn, but
+    /// images are not. With auto, colours are used when writing to a terminal, unless the NO_COLOR
+    /// environment variable is set, or CLICOLOR_FORCE is set (and not 0)
+    #[structopt(long, value_enum, default_value_t, value_name = "WHEN")]
+    pub color: ColorChoice,
+
+    /// How many colours the terminal can show, which the colours of the paper and its images are
+    /// brought down to. By default, this is guessed from the COLORTERM and TERM environment
+    /// variables
+    #[structopt(long, value_enum, value_name = "DEPTH")]
+    pub color_depth: Option<ColorDepthChoice>,
+
     /// Print in debug mode
     #[structopt(long)]
     pub dev: bool,
@@ -83,157 +206,460 @@ pub struct Opts {
     completions: Option<Shell>,
 }
 
-fn normalize(tab_len: usize, source: &str) -> String {
-    source
-        .lines()
-        .map(|line| {
-            let mut len = 0;
-            let line = strip_ansi_codes(line);
-            if line.contains('\t') {
-                line.chars()
-                    .flat_map(|ch| {
-                        if ch == '\t' {
-                            let missing = tab_len - (len % tab_len);
-                            len += missing;
-                            vec![' '; missing]
-                        } else {
-                            len += 1;
-                            vec![ch]
-                        }
-                    })
-                    .collect::<String>()
-                    .into()
-            } else {
-                line
-            }
-        })
-        .map(|line| format!("{}\n", line))
-        .collect::<String>()
+/// The formats that papers can be printed in.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Terminal,
+    Html,
+    Svg,
+    Pdf,
 }
 
-fn print<I>(opts: Opts, sources: I)
-where
-    I: Iterator<Item = Result<String, std::io::Error>>,
-{
-    let h_margin = opts.h_margin.unwrap_or(opts.margin);
-    let v_margin = opts.v_margin.unwrap_or(opts.margin);
-    let terminal_width = terminal_size()
-        .map(|(Width(width), _)| width)
-        .unwrap_or(opts.width as u16) as usize;
-    let width = usize::min(opts.width, terminal_width - 1);
-
-    if width < h_margin * 2 + 40 {
-        eprintln!("The width is too short!");
-        return;
+/// How to draw images, as given on the command line.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ImageProtocolChoice {
+    #[default]
+    Auto,
+    Halfblock,
+    Kitty,
+    Iterm,
+    Sixel,
+}
+
+/// How to round the colours of images, as given on the command line.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DitherChoice {
+    #[default]
+    None,
+    FloydSteinberg,
+    Bayer,
+}
+
+/// How to resample images, as given on the command line.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ImageFilterChoice {
+    #[default]
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos,
+}
+
+/// How many colours the terminal can show, as given on the command line.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorDepthChoice {
+    #[value(name = "truecolor")]
+    TrueColor,
+    #[value(name = "256")]
+    Ansi256,
+    #[value(name = "16")]
+    Ansi16,
+}
+
+/// When to style the paper with colours.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+fn parse_filter(filter: &str) -> Result<(String, String), String> {
+    match filter.split_once('=') {
+        Some((lang, command)) if !lang.is_empty() => Ok((lang.to_owned(), command.to_owned())),
+        _ => Err(String::from("expected LANG=COMMAND")),
     }
+}
 
-    let left_space = match (opts.left, opts.right) {
-        (true, false) => "".to_owned(),
-        (false, true) => " ".repeat(terminal_width.saturating_sub(width) - 1),
-        _ => " ".repeat((terminal_width.saturating_sub(width)) / 2),
-    };
+impl Opts {
+    /// The filters from the config file, with those given on the command line taking precedence.
+    fn filters(&self) -> HashMap<String, Filter> {
+        let mut filters = paper_terminal::configured_filters().unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            HashMap::new()
+        });
+        let given = self
+            .filter
+            .iter()
+            .map(|filter| (filter, false))
+            .chain(self.filter_replace.iter().map(|filter| (filter, true)));
+        for ((lang, command), replace) in given {
+            filters.insert(
+                lang.to_owned(),
+                Filter {
+                    command: command.to_owned(),
+                    replace,
+                },
+            );
+        }
+        filters
+    }
 
-    let stylesheet = Stylesheet::from_file(dirs::active_color().join("paper.syncat"))
-        .unwrap_or_else(|_| {
-            include_str!("default.syncat")
-                .parse::<Stylesheet>()
-                .unwrap()
+    /// Whether to style the paper with colours, following the NO_COLOR and CLICOLOR_FORCE
+    /// conventions when left to decide automatically.
+    fn color(&self) -> bool {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto if var("NO_COLOR").is_some() => false,
+            ColorChoice::Auto if var("CLICOLOR_FORCE").is_some_and(|value| value != "0") => true,
+            ColorChoice::Auto => self.output.is_none() && io::stdout().is_terminal(),
+        }
+    }
+
+    fn image_protocol(&self) -> ImageProtocol {
+        match self.image_protocol {
+            ImageProtocolChoice::Auto if self.output.is_none() && io::stdout().is_terminal() => {
+                ImageProtocol::detect()
+            }
+            ImageProtocolChoice::Auto | ImageProtocolChoice::Halfblock => ImageProtocol::Halfblock,
+            ImageProtocolChoice::Kitty => ImageProtocol::Kitty,
+            ImageProtocolChoice::Iterm => ImageProtocol::Iterm,
+            ImageProtocolChoice::Sixel => ImageProtocol::Sixel,
+        }
+    }
+
+    fn render_options(&self) -> RenderOptions {
+        let size = terminal_size();
+        let terminal_width = size
+            .map(|(Width(width), _)| width)
+            .unwrap_or(self.width as u16) as usize;
+        let terminal_height = size.map(|(_, Height(height))| height as usize);
+        // Without a terminal, there is no height for a percentage to be of.
+        let image_max_height = self.image_max_height.and_then(|extent| match extent {
+            Extent::Cells(lines) => Some(lines),
+            Extent::Percent(_) => terminal_height.map(|height| extent.of(height)),
         });
-    let paper_style: Style = stylesheet
-        .style(&"paper".into())
-        .unwrap_or_default()
-        .try_into()
-        .unwrap_or_default();
-    let shadow_style: Style = stylesheet
-        .style(&"shadow".into())
-        .unwrap_or_default()
-        .try_into()
-        .unwrap_or_default();
-    let blank_line = format!("{}", paper_style.paint(" ".repeat(width)));
-    let end_shadow = format!("{}", shadow_style.paint(" "));
-    let margin = format!("{}", paper_style.paint(" ".repeat(h_margin)));
-    let available_width = width - 2 * h_margin;
+        let mut opts = RenderOptions {
+            h_margin: self.h_margin.unwrap_or(self.margin),
+            v_margin: self.v_margin.unwrap_or(self.margin),
+            width: self.width,
+            terminal_width,
+            position: match (self.left, self.right) {
+                (true, false) => Position::Left,
+                (false, true) => Position::Right,
+                _ => Position::Center,
+            },
+            columns: self.columns.max(1),
+            gutter: self.gutter,
+            page_height: self.page_height,
+            header: self.header.clone().unwrap_or_default(),
+            footer: self.footer.clone().unwrap_or_default(),
+            path: None,
+            toc: self.toc,
+            number_headings: self.number_headings,
+            plain: self.plain,
+            tab_length: self.tab_length,
+            hide_urls: self.hide_urls,
+            no_images: self.no_images,
+            image_protocol: self.image_protocol(),
+            cell_size: if self.output.is_none() && io::stdout().is_terminal() {
+                paper_terminal::terminal_cell_size()
+            } else {
+                DEFAULT_CELL_SIZE
+            },
+            dither: match self.dither {
+                DitherChoice::None => Dither::None,
+                DitherChoice::FloydSteinberg => Dither::FloydSteinberg,
+                DitherChoice::Bayer => Dither::Bayer,
+            },
+            image_filter: match self.image_filter {
+                ImageFilterChoice::Nearest => FilterType::Nearest,
+                ImageFilterChoice::Triangle => FilterType::Triangle,
+                ImageFilterChoice::CatmullRom => FilterType::CatmullRom,
+                ImageFilterChoice::Gaussian => FilterType::Gaussian,
+                ImageFilterChoice::Lanczos => FilterType::Lanczos3,
+            },
+            image_width: self.image_width,
+            image_max_height,
+            syncat: self.syncat,
+            #[cfg(feature = "highlight")]
+            highlight: self.highlight,
+            #[cfg(not(feature = "highlight"))]
+            highlight: false,
+            filters: self.filters(),
+            cache: !self.no_cache,
+            color: self.color(),
+            color_depth: match self.color_depth {
+                None => ColorDepth::detect(),
+                Some(ColorDepthChoice::TrueColor) => ColorDepth::TrueColor,
+                Some(ColorDepthChoice::Ansi256) => ColorDepth::Ansi256,
+                Some(ColorDepthChoice::Ansi16) => ColorDepth::Ansi16,
+            },
+            stylesheet: paper_terminal::active_stylesheet(),
+        };
+        if size.is_none() && self.side_by_side {
+            // Without a terminal to share out between the papers, each is given its full width.
+            opts.terminal_width = paper_terminal::side_by_side_width(self.files.len(), &opts);
+        }
+        opts
+    }
+}
+
+/// A document to print, and the file it was read from.
+struct Source {
+    path: Option<PathBuf>,
+    text: io::Result<String>,
+}
+
+impl Source {
+    fn options(&self, opts: &RenderOptions) -> RenderOptions {
+        RenderOptions {
+            path: self.path.clone(),
+            ..opts.clone()
+        }
+    }
+}
+
+fn render_all(
+    sources: &[Source],
+    opts: &RenderOptions,
+    side_by_side: bool,
+) -> io::Result<Vec<Line>> {
+    if side_by_side {
+        return render_side_by_side(sources, opts);
+    }
+    let mut lines = vec![];
     for source in sources {
-        let source = match source {
-            Ok(source) => normalize(opts.tab_length, &source),
-            Err(error) => {
-                println!("{}", error);
-                continue;
-            }
+        match &source.text {
+            Ok(text) => lines.extend(paper_terminal::render_lines(text, &source.options(opts))?),
+            Err(error) => lines.push(Line::from_iter([Span::content(
+                error.to_string(),
+                Style::default(),
+            )])),
+        }
+    }
+    Ok(lines)
+}
+
+fn render_side_by_side(sources: &[Source], opts: &RenderOptions) -> io::Result<Vec<Line>> {
+    let mut lines = vec![];
+    let mut documents = vec![];
+    for source in sources {
+        match &source.text {
+            Ok(text) => documents.push((text.as_str(), source.options(opts))),
+            Err(error) => lines.push(Line::from_iter([Span::content(
+                error.to_string(),
+                Style::default(),
+            )])),
+        }
+    }
+    lines.extend(paper_terminal::render_side_by_side(&documents)?);
+    Ok(lines)
+}
+
+fn page(opts: &Opts, sources: Vec<Source>) -> io::Result<()> {
+    let render_options = RenderOptions {
+        image_protocol: ImageProtocol::Halfblock,
+        ..opts.render_options()
+    };
+    // The pager shows render errors in place of the paper, so the last one is kept to be reported
+    // once it has closed.
+    let mut render_error = None;
+    pager::page(render_options.color_depth, |terminal_width| {
+        let render_options = RenderOptions {
+            terminal_width,
+            ..render_options.clone()
         };
-        if opts.plain {
-            println!("{}{}", left_space, blank_line);
-            for _ in 0..v_margin {
-                println!("{}{}{}", left_space, blank_line, end_shadow);
-            }
+        let lines = render_all(&sources, &render_options, opts.side_by_side);
+        render_error = lines
+            .as_ref()
+            .err()
+            .map(|error| io::Error::new(error.kind(), error.to_string()));
+        lines
+    })?;
+    render_error.map_or(Ok(()), Err)
+}
 
-            for line in source.lines() {
-                let mut buffer = String::new();
-                let mut indent = None;
-                for word in Words::preserving_whitespace(line) {
-                    if str_width(&buffer) + str_width(&word) > available_width {
-                        println!(
-                            "{}{}{}{}{}{}",
-                            left_space,
-                            margin,
-                            paper_style.paint(&buffer),
-                            paper_style.paint(
-                                " ".repeat(available_width.saturating_sub(str_width(&buffer)))
-                            ),
-                            margin,
-                            shadow_style.paint(" "),
-                        );
-                        buffer.clear();
-                    }
-                    if buffer.is_empty() {
-                        if indent.is_none() {
-                            let indent_len =
-                                word.chars().take_while(|ch| ch.is_whitespace()).count();
-                            indent = Some(word[0..indent_len].to_string());
-                        }
-                        buffer.push_str(indent.as_ref().unwrap());
-                        buffer.push_str(word.trim());
-                    } else {
-                        buffer.push_str(&word);
-                    }
-                }
-                println!(
-                    "{}{}{}{}{}{}",
-                    left_space,
-                    margin,
-                    paper_style.paint(&buffer),
-                    paper_style
-                        .paint(" ".repeat(available_width.saturating_sub(str_width(&buffer)))),
-                    margin,
-                    shadow_style.paint(" "),
-                );
-            }
-            for _ in 0..v_margin {
-                println!("{}{}{}", left_space, blank_line, end_shadow);
-            }
-            println!("{} {}", left_space, shadow_style.paint(" ".repeat(width)));
-        } else if opts.dev {
-            let parser = Parser::new_ext(&source, Options::all());
-            for event in parser {
-                println!("{:?}", event);
-            }
+fn print(opts: &Opts, sources: Vec<Source>) -> io::Result<()> {
+    // Files that can't be read are reported apart from the paper, so that they don't end up in it.
+    let (sources, unreadable): (Vec<_>, Vec<_>) =
+        sources.into_iter().partition(|source| source.text.is_ok());
+    for source in unreadable {
+        if let Err(error) = source.text {
+            eprintln!("{}", error);
+        }
+    }
+    let render_options = opts.render_options();
+    let mut out = output(opts);
+    if opts.side_by_side && !opts.dev {
+        let lines = render_side_by_side(&sources, &render_options)?;
+        ansi::write_lines_with_images(
+            &lines,
+            render_options.image_protocol,
+            render_options.cell_size,
+            render_options.color_depth,
+            &mut out,
+        )?;
+        return out.flush();
+    }
+    for source in &sources {
+        let Ok(text) = &source.text else {
+            continue;
+        };
+        let render_options = source.options(&render_options);
+        if opts.dev {
+            paper_terminal::render_events(text, &render_options, &mut out)?;
         } else {
-            let parser = Parser::new_ext(&source, Options::all());
-            println!("{}{}", left_space, blank_line);
-            for _ in 0..v_margin {
-                println!("{}{}{}", left_space, blank_line, end_shadow);
-            }
+            paper_terminal::render(text, &render_options, &mut out)?;
+        }
+    }
+    out.flush()
+}
 
-            let mut printer =
-                Printer::new(&left_space, &margin, available_width, &stylesheet, &opts);
-            for event in parser {
-                printer.handle(event);
-            }
+/// Reports the error, if there is one, returning whether there was. A closed pipe is not reported,
+/// as it only means that whatever was reading the paper has stopped.
+fn report(result: io::Result<()>) -> bool {
+    match result {
+        Ok(()) => false,
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => false,
+        Err(error) => {
+            eprintln!("{}", error);
+            true
+        }
+    }
+}
 
-            for _ in 0..v_margin {
-                println!("{}{}{}", left_space, blank_line, end_shadow);
+/// Prints the papers in a format other than for the terminal. As there is no terminal to fit within,
+/// the papers are as wide as they ask to be.
+fn export(opts: &Opts, sources: Vec<Source>) -> io::Result<()> {
+    // These formats have colours and images of their own, rather than escape sequences.
+    let render_options = RenderOptions {
+        terminal_width: usize::MAX,
+        position: Position::Left,
+        color: true,
+        color_depth: ColorDepth::TrueColor,
+        image_protocol: ImageProtocol::Halfblock,
+        cell_size: DEFAULT_CELL_SIZE,
+        ..opts.render_options()
+    };
+    let title = sources
+        .iter()
+        .filter_map(|source| source.path.as_ref()?.file_name())
+        .map(|name| name.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let title = if title.is_empty() { "paper" } else { &title };
+    let mut out = output(opts);
+    match opts.output_format {
+        OutputFormat::Pdf => render_pdf(&sources, &render_options)
+            .and_then(|sheets| pdf::write_document(&sheets, title, &mut out)),
+        format => {
+            render_all(&sources, &render_options, opts.side_by_side).and_then(
+                |lines| match format {
+                    OutputFormat::Html => html::write_document(&lines, title, &mut out),
+                    OutputFormat::Svg => svg::write_document(&lines, title, &mut out),
+                    _ => ansi::write_lines(&lines, render_options.color_depth, &mut out),
+                },
+            )
+        }
+    }
+    .and_then(|()| out.flush())
+}
+
+/// Renders each of the papers' sheets to become pages of a PDF. Unless the papers are paginated
+/// already, their sheets are cut to the shape of a sheet of A4.
+fn render_pdf(sources: &[Source], opts: &RenderOptions) -> io::Result<Vec<Vec<Line>>> {
+    let mut sheets = vec![];
+    for source in sources {
+        let opts = source.options(opts);
+        let opts = RenderOptions {
+            page_height: opts.page_height.or(Some(pdf::page_height(opts.width))),
+            ..opts
+        };
+        match &source.text {
+            Ok(text) => sheets.extend(paper_terminal::render_sheets(text, &opts)?),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+    Ok(sheets)
+}
+
+/// Opens the output file, or standard output if there is none. The program exits if the file can't
+/// be created.
+fn output(opts: &Opts) -> Box<dyn Write> {
+    match &opts.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(error) => {
+                eprintln!("{}: {}", path.display(), error);
+                std::process::exit(1);
             }
-            println!("{} {}", left_space, shadow_style.paint(" ".repeat(width)));
+        },
+        None => Box::new(io::stdout().lock()),
+    }
+}
+
+fn read_files(files: &[PathBuf]) -> Vec<Source> {
+    files
+        .iter()
+        .map(|path| Source {
+            path: Some(path.clone()),
+            text: fs::read_to_string(path).map_err(|error| {
+                io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+            }),
+        })
+        .collect()
+}
+
+/// Watches the files, along with the images they refer to.
+fn watcher(files: &[PathBuf]) -> Watcher {
+    let images = files
+        .iter()
+        .filter_map(|path| Some((path, fs::read_to_string(path).ok()?)))
+        .flat_map(|(path, text)| watch::images(&text, path.parent()));
+    Watcher::new(files.iter().cloned().chain(images))
+}
+
+/// Prints the files again every time they change, until interrupted.
+fn watch(opts: &Opts, use_pager: bool) {
+    if use_pager {
+        let render_options = RenderOptions {
+            image_protocol: ImageProtocol::Halfblock,
+            ..opts.render_options()
+        };
+        let mut watcher = watcher(&opts.files);
+        let result = pager::page_watching(
+            render_options.color_depth,
+            |terminal_width| {
+                let render_options = RenderOptions {
+                    terminal_width,
+                    ..render_options.clone()
+                };
+                render_all(&read_files(&opts.files), &render_options, opts.side_by_side)
+            },
+            || {
+                let changed = watcher.changed();
+                if changed {
+                    // The document may refer to different images now.
+                    watcher = self::watcher(&opts.files);
+                }
+                changed
+            },
+        );
+        if let Err(error) = result {
+            eprintln!("{}", error);
+        }
+        return;
+    }
+
+    loop {
+        let mut watcher = watcher(&opts.files);
+        if opts.output.is_none() {
+            execute!(
+                io::stdout(),
+                terminal::Clear(terminal::ClearType::All),
+                cursor::MoveTo(0, 0)
+            )
+            .ok();
+        }
+        report(print(opts, read_files(&opts.files)));
+        while !watcher.changed() {
+            thread::sleep(watch::POLL_INTERVAL);
         }
     }
 }
@@ -249,16 +675,52 @@ fn main() {
         std::process::exit(0);
     }
 
-    if opts.files.is_empty() {
+    if opts.clear_cache {
+        if let Err(error) = paper_terminal::clear_cache() {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
+    if opts.side_by_side && opts.output_format == OutputFormat::Pdf {
+        Opts::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--side-by-side can't be used with --output-format pdf",
+            )
+            .exit();
+    }
+
+    let exporting = opts.output_format != OutputFormat::Terminal;
+    let use_pager = opts.pager
+        && !opts.dev
+        && !exporting
+        && opts.output.is_none()
+        && io::stdout().is_terminal();
+    let sources = if opts.files.is_empty() {
         let mut string = String::new();
         io::stdin().read_to_string(&mut string).unwrap();
-        print(opts, vec![Ok(string)].into_iter());
+        vec![Source {
+            path: None,
+            text: Ok(string),
+        }]
+    } else if opts.watch && !exporting {
+        watch(&opts, use_pager);
+        return;
+    } else {
+        read_files(&opts.files)
+    };
+    // The files that can be read are still printed, but the paper is incomplete without the rest.
+    let unreadable = sources.iter().any(|source| source.text.is_err());
+    let failed = if exporting {
+        report(export(&opts, sources))
+    } else if use_pager {
+        report(page(&opts, sources))
     } else {
-        let sources = opts
-            .files
-            .clone()
-            .into_iter()
-            .map(|path| fs::read_to_string(&path));
-        print(opts, sources);
+        report(print(&opts, sources))
+    };
+    if failed || unreadable {
+        std::process::exit(1);
     }
 }
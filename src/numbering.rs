@@ -0,0 +1,38 @@
+//! Section numbers for headings, such as `1.`, `1.2.` and `1.2.3.`.
+
+/// Counts the headings of each level as they are seen, to number them.
+#[derive(Clone, Debug)]
+pub struct HeadingNumbers {
+    /// The level of the headings that are numbered `1.`, `2.`, and so on. Headings above this
+    /// level are not numbered, and start the count over.
+    start: u8,
+    counters: Vec<usize>,
+}
+
+impl HeadingNumbers {
+    pub fn new(start: u8) -> Self {
+        Self {
+            start,
+            counters: vec![],
+        }
+    }
+
+    /// Counts the next heading, returning its number, if it is numbered. Levels that have been
+    /// skipped over, such as going straight from a first level heading to a third, are numbered
+    /// `0`, so that no two headings share a number.
+    pub fn next(&mut self, level: u8) -> Option<String> {
+        if level < self.start {
+            self.counters.clear();
+            return None;
+        }
+        let depth = usize::from(level - self.start);
+        self.counters.resize(depth + 1, 0);
+        self.counters[depth] += 1;
+        Some(
+            self.counters
+                .iter()
+                .map(|counter| format!("{}.", counter))
+                .collect(),
+        )
+    }
+}
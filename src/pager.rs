@@ -0,0 +1,140 @@
+//! An interactive viewer, which shows the paper on the terminal's alternate screen and allows
+//! scrolling through it.
+
+use crate::ansi;
+use crate::layout::{Line, Span};
+use crate::palette::ColorDepth;
+use crate::watch::POLL_INTERVAL;
+use ansi_term::Style;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, Write};
+
+/// Restores the terminal when dropped, even if the pager exits early due to an error.
+struct Screen<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Screen<W> {
+    fn enter(mut out: W) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self { out })
+    }
+}
+
+impl<W: Write> Drop for Screen<W> {
+    fn drop(&mut self) {
+        execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+enum Action {
+    Quit,
+    Scroll(isize),
+    Top,
+    Bottom,
+    None,
+}
+
+fn action(key: KeyEvent, page: isize) -> Action {
+    if key.kind == KeyEventKind::Release {
+        return Action::None;
+    }
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Action::Quit,
+        KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+        KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => Action::Scroll(1),
+        KeyCode::Char('k') | KeyCode::Up => Action::Scroll(-1),
+        KeyCode::Char('d') => Action::Scroll(page / 2),
+        KeyCode::Char('u') => Action::Scroll(-page / 2),
+        KeyCode::Char(' ') | KeyCode::Char('f') | KeyCode::PageDown => Action::Scroll(page),
+        KeyCode::Char('b') | KeyCode::PageUp => Action::Scroll(-page),
+        KeyCode::Char('g') | KeyCode::Home => Action::Top,
+        KeyCode::Char('G') | KeyCode::End => Action::Bottom,
+        _ => Action::None,
+    }
+}
+
+/// Runs the pager until the user quits. The `render` function is called with the width of the
+/// terminal whenever the paper needs to be laid out, which includes when the terminal is resized,
+/// and the paper is drawn with the colours of a terminal of this `depth`.
+pub fn page<F>(depth: ColorDepth, render: F) -> io::Result<()>
+where
+    F: FnMut(usize) -> io::Result<Vec<Line>>,
+{
+    page_watching(depth, render, || false)
+}
+
+/// Runs the pager like [`page`], but also calls `changed` every [`POLL_INTERVAL`], laying the paper
+/// out again whenever it returns `true`. The scroll position is kept, so that the pager can stay
+/// open while the document is being edited.
+pub fn page_watching<F, C>(depth: ColorDepth, mut render: F, mut changed: C) -> io::Result<()>
+where
+    F: FnMut(usize) -> io::Result<Vec<Line>>,
+    C: FnMut() -> bool,
+{
+    let mut screen = Screen::enter(io::stdout())?;
+    let (mut width, mut height) = terminal::size()?;
+    let mut lines = layout(&mut render, width);
+    let mut top = 0;
+
+    loop {
+        let page = usize::from(height);
+        top = usize::min(top, lines.len().saturating_sub(page));
+        draw(&mut screen.out, &lines[top..], height, depth)?;
+
+        if !event::poll(POLL_INTERVAL)? {
+            if changed() {
+                lines = layout(&mut render, width);
+            }
+            continue;
+        }
+        match event::read()? {
+            Event::Key(key) => match action(key, page as isize) {
+                Action::Quit => break,
+                Action::Scroll(amount) => top = top.saturating_add_signed(amount),
+                Action::Top => top = 0,
+                Action::Bottom => top = usize::MAX,
+                Action::None => {}
+            },
+            Event::Resize(new_width, new_height) => {
+                if new_width != width {
+                    // Keep the same part of the document in view, roughly.
+                    let progress = top as f64 / lines.len().max(1) as f64;
+                    lines = layout(&mut render, new_width);
+                    top = (progress * lines.len() as f64) as usize;
+                }
+                width = new_width;
+                height = new_height;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn layout<F>(render: &mut F, width: u16) -> Vec<Line>
+where
+    F: FnMut(usize) -> io::Result<Vec<Line>>,
+{
+    render(usize::from(width)).unwrap_or_else(|error| {
+        vec![Line::from_iter([Span::content(
+            error.to_string(),
+            Style::default(),
+        )])]
+    })
+}
+
+fn draw<W: Write>(out: &mut W, lines: &[Line], height: u16, depth: ColorDepth) -> io::Result<()> {
+    for row in 0..height {
+        queue!(out, cursor::MoveTo(0, row))?;
+        if let Some(line) = lines.get(usize::from(row)) {
+            ansi::write_line(line, depth, out)?;
+        }
+        queue!(out, terminal::Clear(terminal::ClearType::UntilNewLine))?;
+    }
+    out.flush()
+}
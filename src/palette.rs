@@ -0,0 +1,208 @@
+//! The colours of the terminal's palette, for drawing the paper outside of the terminal, and for
+//! drawing it in terminals that can't show every colour.
+
+use ansi_term::{Colour, Style};
+use std::ops::Range;
+
+/// The colours used for text that is not given any, like the terminal's own.
+pub const FOREGROUND: Colour = Colour::White;
+pub const BACKGROUND: Colour = Colour::Black;
+
+/// The colour that the terminal shows for this colour, assuming xterm's default palette.
+pub fn rgb(colour: Colour) -> [u8; 3] {
+    let index = match colour {
+        Colour::RGB(r, g, b) => return [r, g, b],
+        Colour::Fixed(index) => index,
+        Colour::Black => 0,
+        Colour::Red => 1,
+        Colour::Green => 2,
+        Colour::Yellow => 3,
+        Colour::Blue => 4,
+        Colour::Purple => 5,
+        Colour::Cyan => 6,
+        Colour::White => 7,
+    };
+    ANSI_COLOURS[usize::from(index)].map(|channel| channel as u8)
+}
+
+/// The foreground and background colours the terminal shows text of this style in, after
+/// reversing, hiding, or dimming it. Colours that are left to the terminal are `None`.
+pub fn colours(style: Style) -> (Option<[u8; 3]>, Option<[u8; 3]>) {
+    let (mut foreground, mut background) = (style.foreground, style.background);
+    if style.is_reverse {
+        foreground = Some(style.background.unwrap_or(BACKGROUND));
+        background = Some(style.foreground.unwrap_or(FOREGROUND));
+    }
+    if style.is_hidden {
+        foreground = Some(background.unwrap_or(BACKGROUND));
+    }
+    let mut foreground = foreground.map(rgb);
+    if style.is_dimmed {
+        let top = foreground.unwrap_or(rgb(FOREGROUND));
+        let bottom = background.map_or(rgb(BACKGROUND), rgb);
+        foreground = Some([0, 1, 2].map(|i| ((top[i] as u16 + bottom[i] as u16) / 2) as u8));
+    }
+    (foreground, background.map(rgb))
+}
+
+/// How many colours the terminal can show.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// Any colour, given as RGB.
+    #[default]
+    TrueColor,
+    /// The 256 colours of xterm's palette.
+    Ansi256,
+    /// Only the 16 basic colours, as in the Linux console.
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// Guesses the depth from the `COLORTERM` and `TERM` environment variables. Terminals that say
+    /// nothing of their colours are assumed to show them all.
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let (colorterm, term) = (var("COLORTERM"), var("TERM"));
+        if colorterm == "truecolor" || colorterm == "24bit" || term.ends_with("-direct") {
+            Self::TrueColor
+        } else if term.contains("256") {
+            Self::Ansi256
+        } else if term == "linux" || term == "ansi" || term.contains("16color") {
+            Self::Ansi16
+        } else {
+            Self::TrueColor
+        }
+    }
+
+    /// The indices of the palette that colours are picked from. The first 16 are left out of the
+    /// 256 colours, as terminals often change them.
+    pub fn palette(self) -> Range<usize> {
+        match self {
+            Self::TrueColor | Self::Ansi256 => 16..256,
+            Self::Ansi16 => 0..16,
+        }
+    }
+
+    /// The closest colour to this one that the terminal can show.
+    pub fn colour(self, colour: Colour) -> Colour {
+        match (self, colour) {
+            (Self::TrueColor, _) => colour,
+            (Self::Ansi256, Colour::RGB(..)) => Colour::Fixed(nearest(rgb(colour), self.palette())),
+            (Self::Ansi16, Colour::RGB(..)) | (Self::Ansi16, Colour::Fixed(16..)) => {
+                basic(nearest(rgb(colour), self.palette()))
+            }
+            (Self::Ansi16, Colour::Fixed(index)) => basic(index),
+            _ => colour,
+        }
+    }
+
+    /// The style, with its colours replaced by the closest that the terminal can show.
+    pub fn style(self, style: Style) -> Style {
+        Style {
+            foreground: style.foreground.map(|colour| self.colour(colour)),
+            background: style.background.map(|colour| self.colour(colour)),
+            ..style
+        }
+    }
+}
+
+/// The colour at this index of the palette, named if it is one of the first 8.
+fn basic(index: u8) -> Colour {
+    match index {
+        0 => Colour::Black,
+        1 => Colour::Red,
+        2 => Colour::Green,
+        3 => Colour::Yellow,
+        4 => Colour::Blue,
+        5 => Colour::Purple,
+        6 => Colour::Cyan,
+        7 => Colour::White,
+        index => Colour::Fixed(index),
+    }
+}
+
+/// The SGR parameters that set the foreground, or the background, to this colour of the palette.
+/// The first 16 colours have codes of their own, which terminals that only show those understand,
+/// rather than being given as indices of the 256 colours.
+pub fn sgr(index: u8, background: bool) -> String {
+    let base = if background { 40 } else { 30 };
+    match index {
+        0..8 => (base + index).to_string(),
+        8..16 => (base + 60 + index - 8).to_string(),
+        _ => format!("{};5;{}", base + 8, index),
+    }
+}
+
+/// The index of the colour in the range of the palette that is closest to this one.
+pub fn nearest(colour: [u8; 3], palette: Range<usize>) -> u8 {
+    let distance = |index: &usize| {
+        let [r, g, b] = ANSI_COLOURS[*index];
+        let (dr, dg, db) = (
+            r - colour[0] as i32,
+            g - colour[1] as i32,
+            b - colour[2] as i32,
+        );
+        dr * dr + dg * dg + db * db
+    };
+    palette.min_by_key(distance).unwrap_or_default() as u8
+}
+
+/// The colour written as CSS and SVG expect it.
+pub fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[rustfmt::skip]
+pub static ANSI_COLOURS: [[i32; 3]; 256] = [
+[ 0x00, 0x00, 0x00 ],[ 0x80, 0x00, 0x00 ],[ 0x00, 0x80, 0x00 ],[ 0x80, 0x80, 0x00 ],[ 0x00, 0x00, 0x80 ],
+[ 0x80, 0x00, 0x80 ],[ 0x00, 0x80, 0x80 ],[ 0xc0, 0xc0, 0xc0 ],[ 0x80, 0x80, 0x80 ],[ 0xff, 0x00, 0x00 ],
+[ 0x00, 0xff, 0x00 ],[ 0xff, 0xff, 0x00 ],[ 0x00, 0x00, 0xff ],[ 0xff, 0x00, 0xff ],[ 0x00, 0xff, 0xff ],
+[ 0xff, 0xff, 0xff ],[ 0x00, 0x00, 0x00 ],[ 0x00, 0x00, 0x5f ],[ 0x00, 0x00, 0x87 ],[ 0x00, 0x00, 0xaf ],
+[ 0x00, 0x00, 0xd7 ],[ 0x00, 0x00, 0xff ],[ 0x00, 0x5f, 0x00 ],[ 0x00, 0x5f, 0x5f ],[ 0x00, 0x5f, 0x87 ],
+[ 0x00, 0x5f, 0xaf ],[ 0x00, 0x5f, 0xd7 ],[ 0x00, 0x5f, 0xff ],[ 0x00, 0x87, 0x00 ],[ 0x00, 0x87, 0x5f ],
+[ 0x00, 0x87, 0x87 ],[ 0x00, 0x87, 0xaf ],[ 0x00, 0x87, 0xd7 ],[ 0x00, 0x87, 0xff ],[ 0x00, 0xaf, 0x00 ],
+[ 0x00, 0xaf, 0x5f ],[ 0x00, 0xaf, 0x87 ],[ 0x00, 0xaf, 0xaf ],[ 0x00, 0xaf, 0xd7 ],[ 0x00, 0xaf, 0xff ],
+[ 0x00, 0xd7, 0x00 ],[ 0x00, 0xd7, 0x5f ],[ 0x00, 0xd7, 0x87 ],[ 0x00, 0xd7, 0xaf ],[ 0x00, 0xd7, 0xd7 ],
+[ 0x00, 0xd7, 0xff ],[ 0x00, 0xff, 0x00 ],[ 0x00, 0xff, 0x5f ],[ 0x00, 0xff, 0x87 ],[ 0x00, 0xff, 0xaf ],
+[ 0x00, 0xff, 0xd7 ],[ 0x00, 0xff, 0xff ],[ 0x5f, 0x00, 0x00 ],[ 0x5f, 0x00, 0x5f ],[ 0x5f, 0x00, 0x87 ],
+[ 0x5f, 0x00, 0xaf ],[ 0x5f, 0x00, 0xd7 ],[ 0x5f, 0x00, 0xff ],[ 0x5f, 0x5f, 0x00 ],[ 0x5f, 0x5f, 0x5f ],
+[ 0x5f, 0x5f, 0x87 ],[ 0x5f, 0x5f, 0xaf ],[ 0x5f, 0x5f, 0xd7 ],[ 0x5f, 0x5f, 0xff ],[ 0x5f, 0x87, 0x00 ],
+[ 0x5f, 0x87, 0x5f ],[ 0x5f, 0x87, 0x87 ],[ 0x5f, 0x87, 0xaf ],[ 0x5f, 0x87, 0xd7 ],[ 0x5f, 0x87, 0xff ],
+[ 0x5f, 0xaf, 0x00 ],[ 0x5f, 0xaf, 0x5f ],[ 0x5f, 0xaf, 0x87 ],[ 0x5f, 0xaf, 0xaf ],[ 0x5f, 0xaf, 0xd7 ],
+[ 0x5f, 0xaf, 0xff ],[ 0x5f, 0xd7, 0x00 ],[ 0x5f, 0xd7, 0x5f ],[ 0x5f, 0xd7, 0x87 ],[ 0x5f, 0xd7, 0xaf ],
+[ 0x5f, 0xd7, 0xd7 ],[ 0x5f, 0xd7, 0xff ],[ 0x5f, 0xff, 0x00 ],[ 0x5f, 0xff, 0x5f ],[ 0x5f, 0xff, 0x87 ],
+[ 0x5f, 0xff, 0xaf ],[ 0x5f, 0xff, 0xd7 ],[ 0x5f, 0xff, 0xff ],[ 0x87, 0x00, 0x00 ],[ 0x87, 0x00, 0x5f ],
+[ 0x87, 0x00, 0x87 ],[ 0x87, 0x00, 0xaf ],[ 0x87, 0x00, 0xd7 ],[ 0x87, 0x00, 0xff ],[ 0x87, 0x5f, 0x00 ],
+[ 0x87, 0x5f, 0x5f ],[ 0x87, 0x5f, 0x87 ],[ 0x87, 0x5f, 0xaf ],[ 0x87, 0x5f, 0xd7 ],[ 0x87, 0x5f, 0xff ],
+[ 0x87, 0x87, 0x00 ],[ 0x87, 0x87, 0x5f ],[ 0x87, 0x87, 0x87 ],[ 0x87, 0x87, 0xaf ],[ 0x87, 0x87, 0xd7 ],
+[ 0x87, 0x87, 0xff ],[ 0x87, 0xaf, 0x00 ],[ 0x87, 0xaf, 0x5f ],[ 0x87, 0xaf, 0x87 ],[ 0x87, 0xaf, 0xaf ],
+[ 0x87, 0xaf, 0xd7 ],[ 0x87, 0xaf, 0xff ],[ 0x87, 0xd7, 0x00 ],[ 0x87, 0xd7, 0x5f ],[ 0x87, 0xd7, 0x87 ],
+[ 0x87, 0xd7, 0xaf ],[ 0x87, 0xd7, 0xd7 ],[ 0x87, 0xd7, 0xff ],[ 0x87, 0xff, 0x00 ],[ 0x87, 0xff, 0x5f ],
+[ 0x87, 0xff, 0x87 ],[ 0x87, 0xff, 0xaf ],[ 0x87, 0xff, 0xd7 ],[ 0x87, 0xff, 0xff ],[ 0xaf, 0x00, 0x00 ],
+[ 0xaf, 0x00, 0x5f ],[ 0xaf, 0x00, 0x87 ],[ 0xaf, 0x00, 0xaf ],[ 0xaf, 0x00, 0xd7 ],[ 0xaf, 0x00, 0xff ],
+[ 0xaf, 0x5f, 0x00 ],[ 0xaf, 0x5f, 0x5f ],[ 0xaf, 0x5f, 0x87 ],[ 0xaf, 0x5f, 0xaf ],[ 0xaf, 0x5f, 0xd7 ],
+[ 0xaf, 0x5f, 0xff ],[ 0xaf, 0x87, 0x00 ],[ 0xaf, 0x87, 0x5f ],[ 0xaf, 0x87, 0x87 ],[ 0xaf, 0x87, 0xaf ],
+[ 0xaf, 0x87, 0xd7 ],[ 0xaf, 0x87, 0xff ],[ 0xaf, 0xaf, 0x00 ],[ 0xaf, 0xaf, 0x5f ],[ 0xaf, 0xaf, 0x87 ],
+[ 0xaf, 0xaf, 0xaf ],[ 0xaf, 0xaf, 0xd7 ],[ 0xaf, 0xaf, 0xff ],[ 0xaf, 0xd7, 0x00 ],[ 0xaf, 0xd7, 0x5f ],
+[ 0xaf, 0xd7, 0x87 ],[ 0xaf, 0xd7, 0xaf ],[ 0xaf, 0xd7, 0xd7 ],[ 0xaf, 0xd7, 0xff ],[ 0xaf, 0xff, 0x00 ],
+[ 0xaf, 0xff, 0x5f ],[ 0xaf, 0xff, 0x87 ],[ 0xaf, 0xff, 0xaf ],[ 0xaf, 0xff, 0xd7 ],[ 0xaf, 0xff, 0xff ],
+[ 0xd7, 0x00, 0x00 ],[ 0xd7, 0x00, 0x5f ],[ 0xd7, 0x00, 0x87 ],[ 0xd7, 0x00, 0xaf ],[ 0xd7, 0x00, 0xd7 ],
+[ 0xd7, 0x00, 0xff ],[ 0xd7, 0x5f, 0x00 ],[ 0xd7, 0x5f, 0x5f ],[ 0xd7, 0x5f, 0x87 ],[ 0xd7, 0x5f, 0xaf ],
+[ 0xd7, 0x5f, 0xd7 ],[ 0xd7, 0x5f, 0xff ],[ 0xd7, 0x87, 0x00 ],[ 0xd7, 0x87, 0x5f ],[ 0xd7, 0x87, 0x87 ],
+[ 0xd7, 0x87, 0xaf ],[ 0xd7, 0x87, 0xd7 ],[ 0xd7, 0x87, 0xff ],[ 0xd7, 0xaf, 0x00 ],[ 0xd7, 0xaf, 0x5f ],
+[ 0xd7, 0xaf, 0x87 ],[ 0xd7, 0xaf, 0xaf ],[ 0xd7, 0xaf, 0xd7 ],[ 0xd7, 0xaf, 0xff ],[ 0xd7, 0xd7, 0x00 ],
+[ 0xd7, 0xd7, 0x5f ],[ 0xd7, 0xd7, 0x87 ],[ 0xd7, 0xd7, 0xaf ],[ 0xd7, 0xd7, 0xd7 ],[ 0xd7, 0xd7, 0xff ],
+[ 0xd7, 0xff, 0x00 ],[ 0xd7, 0xff, 0x5f ],[ 0xd7, 0xff, 0x87 ],[ 0xd7, 0xff, 0xaf ],[ 0xd7, 0xff, 0xd7 ],
+[ 0xd7, 0xff, 0xff ],[ 0xff, 0x00, 0x00 ],[ 0xff, 0x00, 0x5f ],[ 0xff, 0x00, 0x87 ],[ 0xff, 0x00, 0xaf ],
+[ 0xff, 0x00, 0xd7 ],[ 0xff, 0x00, 0xff ],[ 0xff, 0x5f, 0x00 ],[ 0xff, 0x5f, 0x5f ],[ 0xff, 0x5f, 0x87 ],
+[ 0xff, 0x5f, 0xaf ],[ 0xff, 0x5f, 0xd7 ],[ 0xff, 0x5f, 0xff ],[ 0xff, 0x87, 0x00 ],[ 0xff, 0x87, 0x5f ],
+[ 0xff, 0x87, 0x87 ],[ 0xff, 0x87, 0xaf ],[ 0xff, 0x87, 0xd7 ],[ 0xff, 0x87, 0xff ],[ 0xff, 0xaf, 0x00 ],
+[ 0xff, 0xaf, 0x5f ],[ 0xff, 0xaf, 0x87 ],[ 0xff, 0xaf, 0xaf ],[ 0xff, 0xaf, 0xd7 ],[ 0xff, 0xaf, 0xff ],
+[ 0xff, 0xd7, 0x00 ],[ 0xff, 0xd7, 0x5f ],[ 0xff, 0xd7, 0x87 ],[ 0xff, 0xd7, 0xaf ],[ 0xff, 0xd7, 0xd7 ],
+[ 0xff, 0xd7, 0xff ],[ 0xff, 0xff, 0x00 ],[ 0xff, 0xff, 0x5f ],[ 0xff, 0xff, 0x87 ],[ 0xff, 0xff, 0xaf ],
+[ 0xff, 0xff, 0xd7 ],[ 0xff, 0xff, 0xff ],[ 0x08, 0x08, 0x08 ],[ 0x12, 0x12, 0x12 ],[ 0x1c, 0x1c, 0x1c ],
+[ 0x26, 0x26, 0x26 ],[ 0x30, 0x30, 0x30 ],[ 0x3a, 0x3a, 0x3a ],[ 0x44, 0x44, 0x44 ],[ 0x4e, 0x4e, 0x4e ],
+[ 0x58, 0x58, 0x58 ],[ 0x60, 0x60, 0x60 ],[ 0x66, 0x66, 0x66 ],[ 0x76, 0x76, 0x76 ],[ 0x80, 0x80, 0x80 ],
+[ 0x8a, 0x8a, 0x8a ],[ 0x94, 0x94, 0x94 ],[ 0x9e, 0x9e, 0x9e ],[ 0xa8, 0xa8, 0xa8 ],[ 0xb2, 0xb2, 0xb2 ],
+[ 0xbc, 0xbc, 0xbc ],[ 0xc6, 0xc6, 0xc6 ],[ 0xd0, 0xd0, 0xd0 ],[ 0xda, 0xda, 0xda ],[ 0xe4, 0xe4, 0xe4 ],
+[ 0xee, 0xee, 0xee ]];
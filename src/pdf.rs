@@ -0,0 +1,400 @@
+//! Writes sheets of paper as the pages of a PDF. The text is set on the same grid of cells as in
+//! the terminal, in DejaVu Sans Mono, which is embedded with only the glyphs that are used. Italic
+//! text is slanted, rather than embedding an italic font too. Images are drawn at their full
+//! resolution, in place of their pixels.
+
+use crate::html::text_decoration;
+use crate::layout::{Line, Placement, SpanKind, placements};
+use crate::palette::{self, FOREGROUND};
+use crate::str_width::str_width;
+use image::{DynamicImage, GenericImageView as _};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::types::{CidFontType, FontFlags, SystemInfo, UnicodeCmap};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::Arc;
+use ttf_parser::Face;
+
+/// The size of each cell of the grid, in points. Cells are twice as tall as they are wide, like in
+/// the terminal, so a paper 92 cells wide is about as wide as a sheet of A4 or Letter.
+const CELL_WIDTH: f32 = 6.0;
+const CELL_HEIGHT: f32 = 12.0;
+/// How far italic text leans.
+const SLANT: f32 = 0.2;
+const COMPRESSION: u8 = 6;
+
+const SYSTEM_INFO: SystemInfo = SystemInfo {
+    registry: Str(b"Adobe"),
+    ordering: Str(b"Identity"),
+    supplement: 0,
+};
+
+/// The height of a sheet of the given width that is the same shape as a sheet of A4.
+pub fn page_height(width: usize) -> usize {
+    (width as f32 * CELL_WIDTH * std::f32::consts::SQRT_2 / CELL_HEIGHT).round() as usize
+}
+
+/// Writes each of the sheets as a page of a PDF with the given title. Each sheet's shadow falls
+/// outside of its page, so it is not drawn.
+pub fn write_document<W: Write>(sheets: &[Vec<Line>], title: &str, out: &mut W) -> io::Result<()> {
+    let mut refs = Refs::default();
+    let catalog = refs.next();
+    let page_tree = refs.next();
+    let info = refs.next();
+    let mut pdf = Pdf::new();
+    let mut fonts = [
+        Font::new(
+            b"F1",
+            b"DejaVuSansMono",
+            include_bytes!("fonts/DejaVuSansMono.ttf"),
+        ),
+        Font::new(
+            b"F2",
+            b"DejaVuSansMono-Bold",
+            include_bytes!("fonts/DejaVuSansMono-Bold.ttf"),
+        ),
+    ];
+    let font_refs = fonts.each_ref().map(|_| refs.next());
+    // Each image is embedded once, however many pages it is drawn on.
+    let mut images: Vec<(Arc<DynamicImage>, Ref)> = vec![];
+
+    let mut pages = vec![];
+    for sheet in sheets {
+        let columns = sheet.iter().map(Line::width).max().unwrap_or(1) - 1;
+        let sheet = &sheet[..sheet.len().saturating_sub(1)];
+        let rows = sheet.len();
+        let height = rows as f32 * CELL_HEIGHT;
+        let mut content = Content::new();
+        let mut used_images = vec![];
+
+        draw_backgrounds(&mut content, sheet, height);
+        for placement in placements(sheet) {
+            let index = match images
+                .iter()
+                .position(|(image, _)| Arc::ptr_eq(image, &placement.image))
+            {
+                Some(index) => index,
+                None => {
+                    images.push((placement.image.clone(), refs.next()));
+                    images.len() - 1
+                }
+            };
+            let name = format!("Im{}", index);
+            draw_image(&mut content, &placement, name.as_bytes(), height);
+            used_images.push(index);
+        }
+        for (row, line) in sheet.iter().enumerate() {
+            draw_text(&mut content, &mut fonts, line, row, height);
+        }
+
+        let page = refs.next();
+        let contents = refs.next();
+        let compressed = compress_to_vec_zlib(&content.finish(), COMPRESSION);
+        pdf.stream(contents, &compressed)
+            .filter(Filter::FlateDecode);
+        let mut writer = pdf.page(page);
+        writer
+            .media_box(Rect::new(0.0, 0.0, columns as f32 * CELL_WIDTH, height))
+            .parent(page_tree)
+            .contents(contents);
+        let mut resources = writer.resources();
+        let mut font_dict = resources.fonts();
+        for (font, id) in fonts.iter().zip(font_refs) {
+            font_dict.pair(Name(font.name), id);
+        }
+        font_dict.finish();
+        let mut x_objects = resources.x_objects();
+        used_images.sort();
+        used_images.dedup();
+        for index in used_images {
+            x_objects.pair(Name(format!("Im{}", index).as_bytes()), images[index].1);
+        }
+        x_objects.finish();
+        resources.finish();
+        writer.finish();
+        pages.push(page);
+    }
+
+    for (font, id) in fonts.iter().zip(font_refs) {
+        font.write(&mut pdf, &mut refs, id);
+    }
+    for (image, id) in &images {
+        write_image(&mut pdf, &mut refs, image, *id);
+    }
+    pdf.catalog(catalog).pages(page_tree);
+    pdf.pages(page_tree).count(pages.len() as i32).kids(pages);
+    pdf.document_info(info).title(TextStr(title));
+    out.write_all(&pdf.finish())
+}
+
+#[derive(Default)]
+struct Refs(i32);
+
+impl Refs {
+    fn next(&mut self) -> Ref {
+        self.0 += 1;
+        Ref::new(self.0)
+    }
+}
+
+/// The position of the top left of a cell, in the PDF's coordinates, which start from the bottom.
+fn position(column: usize, row: usize, height: f32) -> (f32, f32) {
+    (
+        column as f32 * CELL_WIDTH,
+        height - row as f32 * CELL_HEIGHT,
+    )
+}
+
+fn set_fill(content: &mut Content, [r, g, b]: [u8; 3]) {
+    content.set_fill_rgb(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+}
+
+/// Fills in the background of each span, such as the paper and code blocks. Images are drawn over
+/// the paper, so their pixels are not.
+fn draw_backgrounds(content: &mut Content, sheet: &[Line], height: f32) {
+    for (row, line) in sheet.iter().enumerate() {
+        let mut column = 0;
+        let mut run: Option<(usize, usize, [u8; 3])> = None;
+        for span in &line.spans {
+            let start = column;
+            column += span.width();
+            let background = match span.kind {
+                SpanKind::Image => None,
+                _ => palette::colours(span.style).1,
+            };
+            match (&mut run, background) {
+                (Some((_, end, colour)), Some(background)) if *colour == background => {
+                    *end = column;
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some((start, end, colour)) = run.take() {
+                fill_cells(content, start, end, row, height, colour);
+            }
+            run = background.map(|background| (start, column, background));
+        }
+        if let Some((start, end, colour)) = run {
+            fill_cells(content, start, end, row, height, colour);
+        }
+    }
+}
+
+fn fill_cells(
+    content: &mut Content,
+    start: usize,
+    end: usize,
+    row: usize,
+    height: f32,
+    colour: [u8; 3],
+) {
+    let (x, y) = position(start, row + 1, height);
+    set_fill(content, colour);
+    content
+        .rect(x, y, (end - start) as f32 * CELL_WIDTH, CELL_HEIGHT)
+        .fill_nonzero();
+}
+
+fn draw_text(content: &mut Content, fonts: &mut [Font; 2], line: &Line, row: usize, height: f32) {
+    let mut column = 0;
+    for span in &line.spans {
+        let start = column;
+        column += span.width();
+        if span.kind == SpanKind::Image || span.text.trim().is_empty() || span.style.is_hidden {
+            continue;
+        }
+        let font = &mut fonts[usize::from(span.style.is_bold)];
+        let size = font.size();
+        let (x, top) = position(start, row, height);
+        let baseline = top - font.baseline();
+        let (foreground, _) = palette::colours(span.style);
+        set_fill(content, foreground.unwrap_or(palette::rgb(FOREGROUND)));
+        let slant = if span.style.is_italic { SLANT } else { 0.0 };
+        content
+            .begin_text()
+            .set_font(Name(font.name), size)
+            .set_text_matrix([1.0, 0.0, slant, 1.0, x, baseline]);
+        let mut show = content.show_positioned();
+        let mut items = show.items();
+        let mut glyphs = vec![];
+        for ch in span.text.chars() {
+            glyphs.extend(font.encode(ch));
+            // Wide characters take two cells, but the font only leaves room for one.
+            if str_width(&ch.to_string()) == 2 {
+                items.show(Str(&std::mem::take(&mut glyphs)));
+                items.adjust(-CELL_WIDTH / size * 1000.0);
+            }
+        }
+        if !glyphs.is_empty() {
+            items.show(Str(&glyphs));
+        }
+        items.finish();
+        show.finish();
+        content.end_text();
+
+        if let Some(decoration) = text_decoration(span.style) {
+            let width = (column - start) as f32 * CELL_WIDTH;
+            let thickness = size / 16.0;
+            if decoration.contains("underline") {
+                content.rect(x, baseline - 2.0 * thickness, width, thickness);
+            }
+            if decoration.contains("line-through") {
+                content.rect(x, baseline + size / 4.0, width, thickness);
+            }
+            content.fill_nonzero();
+        }
+    }
+}
+
+/// Draws the whole image where its pixels would be, clipped to its rows that are on this sheet.
+fn draw_image(content: &mut Content, placement: &Placement, name: &[u8], height: f32) {
+    let width = placement.columns as f32 * CELL_WIDTH;
+    let visible_rows = placement.visible_rows();
+    let (x, bottom) = position(placement.column, placement.line + visible_rows, height);
+    let image_top = height - (placement.line as f32 - placement.first_row as f32) * CELL_HEIGHT;
+    let image_height = placement.rows as f32 * CELL_HEIGHT;
+    content.save_state();
+    content
+        .rect(x, bottom, width, visible_rows as f32 * CELL_HEIGHT)
+        .clip_nonzero()
+        .end_path();
+    content.transform([width, 0.0, 0.0, image_height, x, image_top - image_height]);
+    content.x_object(Name(name));
+    content.restore_state();
+}
+
+fn write_image(pdf: &mut Pdf, refs: &mut Refs, image: &DynamicImage, id: Ref) {
+    let (width, height) = image.dimensions();
+    let pixels = compress_to_vec_zlib(image.to_rgb8().as_raw(), COMPRESSION);
+    let mask = image.color().has_alpha().then(|| {
+        let alpha = image
+            .to_rgba8()
+            .pixels()
+            .map(|pixel| pixel[3])
+            .collect::<Vec<_>>();
+        (refs.next(), compress_to_vec_zlib(&alpha, COMPRESSION))
+    });
+    let mut xobject = pdf.image_xobject(id, &pixels);
+    xobject
+        .width(width as i32)
+        .height(height as i32)
+        .bits_per_component(8)
+        .filter(Filter::FlateDecode);
+    xobject.color_space().device_rgb();
+    if let Some((mask_id, _)) = &mask {
+        xobject.s_mask(*mask_id);
+    }
+    xobject.finish();
+    if let Some((mask_id, alpha)) = &mask {
+        let mut xobject = pdf.image_xobject(*mask_id, alpha);
+        xobject
+            .width(width as i32)
+            .height(height as i32)
+            .bits_per_component(8)
+            .filter(Filter::FlateDecode);
+        xobject.color_space().device_gray();
+    }
+}
+
+/// An embedded font, which keeps track of the glyphs that have been used, so that only those are
+/// embedded.
+struct Font {
+    name: &'static [u8],
+    base_font: &'static [u8],
+    data: &'static [u8],
+    face: Face<'static>,
+    /// The glyphs that have been used, and the text that each was used for.
+    glyphs: BTreeMap<u16, char>,
+}
+
+impl Font {
+    fn new(name: &'static [u8], base_font: &'static [u8], data: &'static [u8]) -> Self {
+        Self {
+            name,
+            base_font,
+            data,
+            face: Face::parse(data, 0).unwrap(),
+            glyphs: BTreeMap::new(),
+        }
+    }
+
+    /// Converts font units to the thousandths of the font size that PDFs measure fonts in.
+    fn scale(&self, units: impl Into<f32>) -> f32 {
+        units.into() * 1000.0 / self.face.units_per_em() as f32
+    }
+
+    fn advance(&self) -> f32 {
+        let glyph = self.face.glyph_index('M').unwrap_or_default();
+        self.scale(self.face.glyph_hor_advance(glyph).unwrap_or_default())
+    }
+
+    /// The size of the font that makes each character exactly the width of a cell.
+    fn size(&self) -> f32 {
+        CELL_WIDTH * 1000.0 / self.advance()
+    }
+
+    /// How far below the top of its cell the text sits, so that it is centred in the cell.
+    fn baseline(&self) -> f32 {
+        let size = self.size() / 1000.0;
+        let ascender = self.scale(self.face.ascender()) * size;
+        let descender = self.scale(self.face.descender()) * size;
+        (CELL_HEIGHT - (ascender - descender)) / 2.0 + ascender
+    }
+
+    /// The glyph for the character, encoded as the font expects it.
+    fn encode(&mut self, ch: char) -> [u8; 2] {
+        let glyph = self.face.glyph_index(ch).unwrap_or_default().0;
+        self.glyphs.entry(glyph).or_insert(ch);
+        glyph.to_be_bytes()
+    }
+
+    fn write(&self, pdf: &mut Pdf, refs: &mut Refs, id: Ref) {
+        let cid_font = refs.next();
+        let descriptor = refs.next();
+        let cmap = refs.next();
+        let file = refs.next();
+
+        pdf.type0_font(id)
+            .base_font(Name(self.base_font))
+            .encoding_predefined(Name(b"Identity-H"))
+            .descendant_font(cid_font)
+            .to_unicode(cmap);
+        pdf.cid_font(cid_font)
+            .subtype(CidFontType::Type2)
+            .base_font(Name(self.base_font))
+            .system_info(SYSTEM_INFO)
+            .font_descriptor(descriptor)
+            .default_width(self.advance())
+            .cid_to_gid_map_predefined(Name(b"Identity"));
+
+        let bbox = self.face.global_bounding_box();
+        pdf.font_descriptor(descriptor)
+            .name(Name(self.base_font))
+            .flags(FontFlags::FIXED_PITCH | FontFlags::NON_SYMBOLIC)
+            .bbox(Rect::new(
+                self.scale(bbox.x_min),
+                self.scale(bbox.y_min),
+                self.scale(bbox.x_max),
+                self.scale(bbox.y_max),
+            ))
+            .italic_angle(0.0)
+            .ascent(self.scale(self.face.ascender()))
+            .descent(self.scale(self.face.descender()))
+            .cap_height(self.scale(self.face.capital_height().unwrap_or(self.face.ascender())))
+            .stem_v(80.0)
+            .font_file2(file);
+
+        let mut unicode = UnicodeCmap::new(Name(b"Custom"), SYSTEM_INFO);
+        for (&glyph, &ch) in &self.glyphs {
+            unicode.pair(glyph, ch);
+        }
+        pdf.cmap(cmap, &unicode.finish());
+
+        let glyphs = self.glyphs.keys().copied().collect::<Vec<_>>();
+        let subset = subsetter::subset(self.data, 0, subsetter::Profile::pdf(&glyphs))
+            .unwrap_or_else(|_| self.data.to_vec());
+        pdf.stream(file, &compress_to_vec_zlib(&subset, COMPRESSION))
+            .filter(Filter::FlateDecode);
+    }
+}
@@ -1,14 +1,23 @@
-use crate::str_width;
+use crate::ansi;
+use crate::filter::{Block, Outputs};
+use crate::graphics::ImageProtocol;
+#[cfg(feature = "highlight")]
+use crate::highlight;
+use crate::images;
+use crate::layout::{self, Body, Heading, Line, Picture, Span, SpanKind};
+use crate::numbering::HeadingNumbers;
+use crate::str_width::{str_width, truncate};
 use crate::table::Table;
 use crate::termpix;
+use crate::toc;
 use crate::words::Words;
 use ansi_term::Style;
-use console::AnsiCodeIterator;
-use image::{self, GenericImageView as _};
-use pulldown_cmark::{Alignment, BlockQuoteKind, CodeBlockKind, Event, HeadingLevel, Tag, TagEnd};
+use pulldown_cmark::{
+    Alignment, BlockQuoteKind, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd,
+};
 use std::convert::{TryFrom, TryInto};
-use std::io::{Read as _, Write as _};
-use std::process::{Command, Stdio};
+use std::path::Path;
+use std::sync::Arc;
 use syncat_stylesheet::{Query, Stylesheet};
 
 #[derive(Debug, PartialEq)]
@@ -140,38 +149,196 @@ impl Scope {
     }
 }
 
+pub(crate) fn resolve_scopes(
+    stylesheet: &Stylesheet,
+    scopes: &[&str],
+    token: Option<&str>,
+) -> Style {
+    if scopes.is_empty() {
+        return Style::default();
+    }
+    let mut query = Query::new(scopes[0], token.unwrap_or(scopes[0]));
+    let mut index = vec![];
+    for scope in &scopes[1..] {
+        query[&index[..]].add_child(Query::new(scope, token.unwrap_or(scope)));
+        index.push(0);
+    }
+    stylesheet
+        .style(&query)
+        .unwrap_or_default()
+        .try_into()
+        .unwrap_or_default()
+}
+
+/// The language of a code block, which is the first word of its info string. The words after it
+/// are attributes, such as `ignore` in `rust ignore`.
+fn language(info: &str) -> &str {
+    info.split_whitespace().next().unwrap_or("")
+}
+
+/// The width of each column of the body, when it is split into the requested number of columns.
+pub(crate) fn column_width(width: usize, opts: &crate::RenderOptions) -> usize {
+    if opts.columns <= 1 {
+        return width;
+    }
+    width.saturating_sub((opts.columns - 1) * opts.gutter) / opts.columns
+}
+
+/// The block to run code of this language through, if it has a filter or syncat was requested.
+fn command_block(
+    opts: &crate::RenderOptions,
+    lang: &str,
+    code: &str,
+    width: usize,
+) -> Option<Block> {
+    match opts.filters.get(lang) {
+        Some(filter) => Some(Block::shell(&filter.command, code, width)),
+        None if opts.syncat => Some(Block::syncat(lang, code, width)),
+        None => None,
+    }
+}
+
+/// The code blocks of the document that go through a filter or syncat, at the width they are
+/// printed at. They are found from the document's events alone, following only the scopes that
+/// take up width, so that they can all be filtered at once before the document is printed.
+pub(crate) fn command_blocks(
+    source: &str,
+    width: usize,
+    opts: &crate::RenderOptions,
+) -> Vec<Block> {
+    // Only headings span the columns, and code blocks are never in headings.
+    let width = column_width(width, opts);
+    let mut scope = vec![Scope::Paper];
+    let mut code = String::new();
+    let mut blocks = vec![];
+    for event in Parser::new_ext(source, Options::all()) {
+        match event {
+            Event::Start(Tag::MetadataBlock(..) | Tag::CodeBlock(CodeBlockKind::Indented)) => {
+                scope.push(Scope::CodeBlock(String::new()));
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                scope.push(Scope::CodeBlock(info.into_string()));
+            }
+            Event::Start(Tag::BlockQuote(kind)) => scope.push(Scope::BlockQuote(kind)),
+            Event::Start(Tag::List(start_index)) => scope.push(Scope::List(start_index)),
+            Event::Start(Tag::Item) => {
+                let index = match scope.last() {
+                    Some(&Scope::List(index)) => index,
+                    _ => None,
+                };
+                scope.push(Scope::ListItem(index, false));
+            }
+            Event::Start(Tag::DefinitionListDefinition) => scope.push(Scope::Definition),
+            Event::Start(Tag::FootnoteDefinition(..)) => scope.push(Scope::FootnoteContent),
+            Event::Text(text) if matches!(scope.last(), Some(Scope::CodeBlock(..))) => {
+                code.push_str(&text);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                let Some(Scope::CodeBlock(info)) = scope.last() else {
+                    continue;
+                };
+                let lang = language(info).to_owned();
+                let code = std::mem::take(&mut code);
+                let block = match opts.filters.get(&lang) {
+                    // Replaced code blocks are printed without the code block's frame.
+                    Some(filter) if filter.replace => {
+                        let frame = &scope[..scope.len() - 1];
+                        let available_width = width
+                            .saturating_sub(frame.iter().map(Scope::prefix_len).sum())
+                            .saturating_sub(frame.iter().map(Scope::suffix_len).sum());
+                        Some(Block::shell(&filter.command, &code, available_width))
+                    }
+                    _ => {
+                        let frame_width = scope
+                            .iter_mut()
+                            .map(|scope| str_width(&scope.prefix()) + str_width(&scope.suffix()))
+                            .sum();
+                        command_block(opts, &lang, &code, width.saturating_sub(frame_width))
+                    }
+                };
+                blocks.extend(block);
+                scope.pop();
+            }
+            Event::End(TagEnd::Item) => {
+                scope.pop();
+                if let Some(Scope::List(index)) = scope.last_mut() {
+                    *index = index.map(|x| x + 1);
+                }
+            }
+            Event::End(
+                TagEnd::MetadataBlock(..)
+                | TagEnd::BlockQuote(..)
+                | TagEnd::List(..)
+                | TagEnd::DefinitionListDefinition
+                | TagEnd::FootnoteDefinition,
+            ) => {
+                scope.pop();
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
 pub struct Printer<'a> {
-    centering: &'a str,
-    margin: &'a str,
     stylesheet: &'a Stylesheet,
-    opts: &'a crate::Opts,
+    opts: &'a crate::RenderOptions,
+    outputs: &'a Outputs,
     width: usize,
+    /// The width of each column, and the width of the whole body, which spanning headings use.
+    column_width: usize,
+    full_width: usize,
+    /// The first line of the content that is yet to be arranged into columns.
+    flow_start: usize,
+    /// The number of lines of the body that fit on each page, if it is split into pages.
+    body_height: Option<usize>,
     buffer: String,
     table: (Vec<String>, Vec<Vec<String>>),
-    content: String,
+    content: Vec<Span>,
     scope: Vec<Scope>,
     empty_queued: bool,
+    offset: Option<usize>,
+    lines: Vec<Line>,
+    headings: Vec<Heading>,
+    numbers: Option<HeadingNumbers>,
 }
 
 impl<'a> Printer<'a> {
     pub fn new(
-        centering: &'a str,
-        margin: &'a str,
         width: usize,
-        stylesheet: &'a Stylesheet,
-        opts: &'a crate::Opts,
+        body_height: Option<usize>,
+        opts: &'a crate::RenderOptions,
+        outputs: &'a Outputs,
     ) -> Printer<'a> {
+        let column_width = column_width(width, opts);
         Printer {
-            centering,
-            margin,
-            width,
-            stylesheet,
+            width: column_width,
+            column_width,
+            full_width: width,
+            flow_start: 0,
+            body_height,
+            stylesheet: &opts.stylesheet,
             opts,
+            outputs,
             buffer: String::new(),
             table: (vec![], vec![]),
-            content: String::new(),
+            content: vec![],
             scope: vec![Scope::Paper],
             empty_queued: false,
+            offset: None,
+            lines: vec![],
+            headings: vec![],
+            numbers: opts.number_headings.map(HeadingNumbers::new),
+        }
+    }
+
+    /// Finishes printing, returning the paper's body.
+    pub fn finish(mut self) -> Body {
+        self.flush();
+        self.balance_columns();
+        Body {
+            lines: self.lines,
+            headings: self.headings,
         }
     }
 
@@ -181,17 +348,73 @@ impl<'a> Printer<'a> {
             .fold(0, |len, scope| len + scope.prefix_len())
     }
 
+    fn in_columns(&self) -> bool {
+        self.column_width < self.full_width
+    }
+
+    /// Arranges the lines printed since the last spanning heading into balanced columns, side by
+    /// side, reading down the first column before continuing at the top of the next. When the body
+    /// is split into pages, each page's worth of lines is balanced on its own, so that the columns
+    /// are read in order from one page to the next.
+    fn balance_columns(&mut self) {
+        if !self.in_columns() || self.flow_start >= self.lines.len() {
+            return;
+        }
+        let mut flow = self.lines.split_off(self.flow_start);
+        let mut flow_line = self.flow_start;
+        let columns = self.opts.columns;
+        let paper_style = self.paper_style();
+        let filler =
+            self.full_width - columns * self.column_width - (columns - 1) * self.opts.gutter;
+        while !flow.is_empty() {
+            let page_rows = match self.body_height {
+                Some(body_height) => body_height - self.lines.len() % body_height,
+                None => flow.len(),
+            };
+            let rest = flow.split_off(usize::min(flow.len(), page_rows * columns));
+            let page = std::mem::replace(&mut flow, rest);
+            let rows = page.len().div_ceil(columns);
+            let start = self.lines.len();
+            for heading in &mut self.headings {
+                if (flow_line..flow_line + page.len()).contains(&heading.line) {
+                    heading.line = start + (heading.line - flow_line) % rows;
+                }
+            }
+            for row in 0..rows {
+                let mut line = Line::new();
+                for column in 0..columns {
+                    if column > 0 {
+                        line.push(Span::margin(" ".repeat(self.opts.gutter), paper_style));
+                    }
+                    match page.get(column * rows + row) {
+                        Some(column_line) => line.extend(column_line.spans.iter().cloned()),
+                        None => line.push(Span::margin(" ".repeat(self.column_width), paper_style)),
+                    }
+                }
+                line.push(Span::margin(" ".repeat(filler), paper_style));
+                self.lines.push(line);
+            }
+            flow_line += page.len();
+        }
+        self.flow_start = self.lines.len();
+    }
+
+    /// Top level headings of the first level span all the columns.
+    fn is_spanning_heading(&self, level: HeadingLevel) -> bool {
+        self.in_columns() && level == HeadingLevel::H1 && self.scope.len() == 1
+    }
+
     fn suffix_len(&self) -> usize {
         self.scope
             .iter()
             .fold(0, |len, scope| len + scope.suffix_len())
     }
 
-    fn prefix(&mut self) -> (String, usize) {
+    fn prefix(&mut self) -> (Vec<Span>, usize) {
         self.prefix2(None)
     }
 
-    fn prefix2(&mut self, extra_scopes: Option<&[&str]>) -> (String, usize) {
+    fn prefix2(&mut self, extra_scopes: Option<&[&str]>) -> (Vec<Span>, usize) {
         let stylesheet = self.stylesheet;
         self.scope
             .iter_mut()
@@ -199,18 +422,22 @@ impl<'a> Printer<'a> {
                 scopes.push(scope.name());
                 let prefix = scope.prefix();
                 let mut all_scopes = scopes.clone();
-                all_scopes.append(&mut extra_scopes.unwrap_or(&[]).to_vec());
-                let style = Self::resolve_scopes(&stylesheet, &all_scopes, Some("prefix"));
-                Some((format!("{}", style.paint(&prefix)), str_width(&prefix)))
+                all_scopes.extend_from_slice(extra_scopes.unwrap_or(&[]));
+                let style = resolve_scopes(stylesheet, &all_scopes, Some("prefix"));
+                Some(Span::prefix(prefix, style))
+            })
+            .fold((vec![], 0), |(mut spans, len), span| {
+                let span_len = span.width();
+                spans.push(span);
+                (spans, len + span_len)
             })
-            .fold((String::new(), 0), |(s, c), (s2, c2)| (s + &s2, c + c2))
     }
 
-    fn suffix(&mut self) -> (String, usize) {
+    fn suffix(&mut self) -> (Vec<Span>, usize) {
         self.suffix2(None)
     }
 
-    fn suffix2(&mut self, extra_scopes: Option<&[&str]>) -> (String, usize) {
+    fn suffix2(&mut self, extra_scopes: Option<&[&str]>) -> (Vec<Span>, usize) {
         let stylesheet = self.stylesheet;
         self.scope
             .iter_mut()
@@ -218,11 +445,15 @@ impl<'a> Printer<'a> {
                 scopes.push(scope.name());
                 let suffix = scope.suffix();
                 let mut all_scopes = scopes.clone();
-                all_scopes.append(&mut extra_scopes.unwrap_or(&[]).to_vec());
-                let style = Self::resolve_scopes(&stylesheet, &all_scopes, Some("suffix"));
-                Some((format!("{}", style.paint(&suffix)), str_width(&suffix)))
+                all_scopes.extend_from_slice(extra_scopes.unwrap_or(&[]));
+                let style = resolve_scopes(stylesheet, &all_scopes, Some("suffix"));
+                Some(Span::prefix(suffix, style))
+            })
+            .fold((vec![], 0), |(mut spans, len), span| {
+                let span_len = span.width();
+                spans.insert(0, span);
+                (spans, len + span_len)
             })
-            .fold((String::new(), 0), |(s, c), (s2, c2)| (s2 + &s, c + c2))
     }
 
     fn style3(&self, extra_scopes: Option<&[&str]>, token: Option<&str>) -> Style {
@@ -230,24 +461,7 @@ impl<'a> Printer<'a> {
         if let Some(extras) = extra_scopes {
             scope_names.append(&mut extras.to_vec());
         }
-        Self::resolve_scopes(&self.stylesheet, &scope_names, token)
-    }
-
-    fn resolve_scopes(stylesheet: &Stylesheet, scopes: &[&str], token: Option<&str>) -> Style {
-        if scopes.is_empty() {
-            return Style::default();
-        }
-        let mut query = Query::new(scopes[0], token.unwrap_or(scopes[0]));
-        let mut index = vec![];
-        for scope in &scopes[1..] {
-            query[&index[..]].add_child(Query::new(*scope, token.unwrap_or(scope)));
-            index.push(0);
-        }
-        stylesheet
-            .style(&query)
-            .unwrap_or_default()
-            .try_into()
-            .unwrap_or_default()
+        resolve_scopes(self.stylesheet, &scope_names, token)
     }
 
     fn style2(&self, token: Option<&str>) -> Style {
@@ -258,20 +472,41 @@ impl<'a> Printer<'a> {
         self.style2(None)
     }
 
-    fn shadow(&self) -> String {
-        format!(
-            "{}",
-            Style::try_from(self.stylesheet.style(&"shadow".into()).unwrap_or_default())
-                .unwrap_or_default()
-                .paint(" ")
-        )
-    }
-
     fn paper_style(&self) -> Style {
         Style::try_from(self.stylesheet.style(&"paper".into()).unwrap_or_default())
             .unwrap_or_default()
     }
 
+    /// The size to draw the image at, in pixels of half a cell each. With half blocks, each column
+    /// of the image's pixels takes a column of cells, but with a graphics protocol, the image takes
+    /// up as many cells as it would at its own size. Either way, its height is chosen to keep its
+    /// shape in cells of the terminal's size, and it is shrunk to fit the width and height allowed.
+    fn image_size(&self, (width, height): (u32, u32), available_width: usize) -> (u32, u32) {
+        let (cell_width, cell_height) = self.opts.cell_size;
+        // How many of the terminal's pixels each of the image's pixels is drawn across.
+        let scale = match self.opts.image_protocol {
+            ImageProtocol::Halfblock => cell_width as f64,
+            _ => 1.0,
+        };
+        let mut columns = width as f64 * scale / cell_width as f64;
+        let mut rows = height as f64 * scale / cell_height as f64;
+
+        let max_columns = match self.opts.image_width {
+            Some(extent) => usize::min(extent.of(available_width), available_width),
+            None => available_width,
+        };
+        let max_rows = self.opts.image_max_height.unwrap_or(usize::MAX);
+        let fit = f64::min(max_columns as f64 / columns, max_rows as f64 / rows);
+        if fit < 1.0 {
+            columns *= fit;
+            rows *= fit;
+        }
+        // Each line holds two rows of pixels, so the image is always an even number of pixels tall.
+        let columns = (columns.round() as u32).max(1);
+        let rows = (rows.round() as u32).max(1);
+        (columns, rows * 2)
+    }
+
     fn queue_empty(&mut self) {
         self.empty_queued = true;
     }
@@ -279,44 +514,85 @@ impl<'a> Printer<'a> {
     fn empty(&mut self) {
         let (prefix, prefix_len) = self.prefix();
         let (suffix, suffix_len) = self.suffix();
-        println!(
-            "{}{}{}{}{}{}{}",
-            self.centering,
-            self.margin,
-            prefix,
-            self.paper_style().paint(
-                " ".repeat(
-                    self.width
-                        .saturating_sub(prefix_len)
-                        .saturating_sub(suffix_len)
-                )
+        let mut line = Line::new();
+        line.extend(prefix);
+        line.push(Span::margin(
+            " ".repeat(
+                self.width
+                    .saturating_sub(prefix_len)
+                    .saturating_sub(suffix_len),
             ),
-            suffix,
-            self.margin,
-            self.shadow(),
-        );
+            self.paper_style(),
+        ));
+        line.extend(suffix);
+        self.lines.push(line);
         self.empty_queued = false;
     }
 
     fn print_rule(&mut self) {
         let (prefix, prefix_len) = self.prefix();
         let (suffix, suffix_len) = self.suffix();
-        println!(
-            "{}{}{}{}{}{}{}",
-            self.centering,
-            self.margin,
-            prefix,
-            self.style().paint(
+        let mut line = Line::new();
+        line.extend(prefix);
+        line.push(
+            Span::content(
                 "─".repeat(
                     self.width
                         .saturating_sub(prefix_len)
-                        .saturating_sub(suffix_len)
-                )
-            ),
-            suffix,
-            self.margin,
-            self.shadow(),
+                        .saturating_sub(suffix_len),
+                ),
+                self.style(),
+            )
+            .at(self.offset),
         );
+        line.extend(suffix);
+        self.lines.push(line);
+    }
+
+    /// Prints the table of contents, with each entry indented by its level and followed by the
+    /// number of the page it is on, if the document has been paginated.
+    pub fn print_toc(&mut self, entries: &[toc::Entry]) {
+        self.flush();
+        if self.empty_queued {
+            self.empty();
+        }
+        let top_level = entries.iter().map(|entry| entry.level).min().unwrap_or(1);
+        let style = self.style3(Some(&["toc"]), None);
+        let leader_style = self.style3(Some(&["toc"]), Some("leader"));
+        let page_style = self.style3(Some(&["toc"]), Some("page-number"));
+        for entry in entries {
+            let (prefix, prefix_len) = self.prefix();
+            let (suffix, suffix_len) = self.suffix();
+            let available_width = self
+                .width
+                .saturating_sub(prefix_len)
+                .saturating_sub(suffix_len);
+            let indent = " ".repeat(4 * usize::from(entry.level - top_level));
+            let page = entry.page.map(|page| page.to_string()).unwrap_or_default();
+            let page_width = if page.is_empty() { 0 } else { page.len() + 2 };
+            let text_width = available_width.saturating_sub(indent.len() + page_width);
+            let text = if str_width(&entry.text) > text_width {
+                truncate(&entry.text, text_width.saturating_sub(1)) + "…"
+            } else {
+                entry.text.clone()
+            };
+            let remaining = available_width.saturating_sub(indent.len() + str_width(&text));
+
+            let mut line = Line::new();
+            line.extend(prefix);
+            line.push(Span::margin(indent, self.paper_style()));
+            line.push(Span::content(text, style));
+            if page.is_empty() {
+                line.push(Span::margin(" ".repeat(remaining), self.paper_style()));
+            } else {
+                let leader = format!(" {} ", ".".repeat(remaining.saturating_sub(page_width)));
+                line.push(Span::prefix(leader, leader_style));
+                line.push(Span::content(page, page_style));
+            }
+            line.extend(suffix);
+            self.lines.push(line);
+        }
+        self.queue_empty();
     }
 
     fn print_table(&mut self) {
@@ -325,170 +601,222 @@ impl<'a> Printer<'a> {
         } else {
             return;
         };
-        let (heading, rows) = std::mem::replace(&mut self.table, (vec![], vec![]));
+        let (heading, rows) = std::mem::take(&mut self.table);
         let available_width = self
             .width
             .saturating_sub(self.prefix_len())
             .saturating_sub(self.suffix_len());
-        let table_str =
-            Table::new(heading, rows, available_width).print(self.paper_style(), alignments);
-        for line in table_str.lines() {
+        let table_lines = Table::new(heading, rows, available_width).print(alignments);
+        for table_line in table_lines {
             let (prefix, _) = self.prefix();
             let (suffix, _) = self.suffix();
-            println!(
-                "{}{}{}{}{}{}{}{}",
-                self.centering,
-                self.margin,
-                line,
-                prefix,
-                self.paper_style()
-                    .paint(" ".repeat(available_width.saturating_sub(str_width(line)))),
-                suffix,
-                self.margin,
-                self.shadow(),
-            );
+            let mut line = Line::new();
+            let padding = available_width.saturating_sub(str_width(&table_line));
+            line.extend(prefix);
+            line.push(Span::content(table_line, self.paper_style()).at(self.offset));
+            line.push(Span::margin(" ".repeat(padding), self.paper_style()));
+            line.extend(suffix);
+            self.lines.push(line);
         }
     }
 
-    fn flush_buffer(&mut self) {
-        match self.scope.last() {
-            Some(Scope::CodeBlock(lang)) => {
-                let language_context = if lang.is_empty() || !self.opts.syncat {
-                    String::from("txt")
-                } else {
-                    lang.to_owned()
-                };
-                let style = self.style3(Some(&[&language_context[..]]), None);
-                let lang = lang.to_owned();
-                let mut first_prefix = Some(self.prefix2(Some(&[&language_context[..]])));
-                let mut first_suffix = Some(self.suffix2(Some(&[&language_context[..]])));
-
-                let available_width = self
-                    .width
-                    .saturating_sub(first_prefix.as_ref().unwrap().1)
-                    .saturating_sub(first_suffix.as_ref().unwrap().1);
-                let buffer = std::mem::replace(&mut self.buffer, String::new());
-                let buffer = if self.opts.syncat {
-                    let syncat = Command::new("syncat")
-                        .args(&["-l", &lang, "-w", &available_width.to_string()])
-                        .stdin(Stdio::piped())
-                        .stdout(Stdio::piped())
-                        .spawn();
-                    match syncat {
-                        Ok(syncat) => {
-                            {
-                                let mut stdin = syncat.stdin.unwrap();
-                                write!(stdin, "{}", buffer).unwrap();
-                            }
-                            let mut output = String::new();
-                            syncat.stdout.unwrap().read_to_string(&mut output).unwrap();
-                            output
-                        }
-                        Err(error) => {
-                            eprintln!("{}", error);
-                            buffer.to_owned()
-                        }
-                    }
-                } else {
-                    buffer
-                        .lines()
-                        .map(|mut line| {
-                            let mut output = String::new();
-                            while str_width(&line) > available_width {
-                                let not_too_wide = {
-                                    let mut acc = 0;
-                                    move |ch: &char| {
-                                        acc += str_width(&ch.to_string());
-                                        acc < available_width
-                                    }
-                                };
-                                let prefix =
-                                    line.chars().take_while(not_too_wide).collect::<String>();
-                                output = format!("{}{}\n", output, prefix);
-                                line = &line[prefix.len()..];
-                            }
-                            format!(
-                                "{}{}{}\n",
-                                output,
-                                line,
-                                " ".repeat(available_width.saturating_sub(str_width(&line)))
-                            )
-                        })
-                        .collect()
-                };
+    /// Highlights the code with the grammars built into the binary, if requested and there is one
+    /// for the language.
+    #[cfg(feature = "highlight")]
+    fn highlight(
+        &self,
+        code: &str,
+        lang: &str,
+        language_context: &str,
+        style: Style,
+    ) -> Option<Vec<Vec<Span>>> {
+        if !self.opts.highlight {
+            return None;
+        }
+        let mut scopes: Vec<_> = self.scope.iter().map(Scope::name).collect();
+        scopes.push(language_context);
+        highlight::highlight(code, lang, self.stylesheet, &scopes, style)
+    }
 
-                let (prefix, _) = first_prefix
-                    .take()
-                    .unwrap_or_else(|| self.prefix2(Some(&[&language_context[..]])));
-                let (suffix, _) = first_suffix
-                    .take()
-                    .unwrap_or_else(|| self.suffix2(Some(&[&language_context[..]])));
-                println!(
-                    "{}{}{}{}{}{}{}",
-                    self.centering,
-                    self.margin,
-                    prefix,
-                    style.paint(" ".repeat(available_width)),
-                    suffix,
-                    self.margin,
-                    self.shadow(),
-                );
-
-                for line in buffer.lines() {
-                    let width = str_width(line);
-                    let (prefix, _) = self.prefix2(Some(&[&language_context[..]]));
-                    let (suffix, _) = self.suffix2(Some(&[&language_context[..]]));
-                    print!(
-                        "{}{}{}{}",
-                        self.centering,
-                        self.margin,
-                        prefix,
-                        style.prefix(),
-                    );
-                    for (s, is_ansi) in AnsiCodeIterator::new(line) {
-                        if is_ansi {
-                            if s == "\u{1b}[0m" {
-                                print!("{}{}", s, style.prefix());
-                            } else {
-                                print!("{}{}", style.prefix(), s);
-                            }
-                        } else {
-                            print!("{}", s);
+    #[cfg(not(feature = "highlight"))]
+    fn highlight(&self, _: &str, _: &str, _: &str, _: Style) -> Option<Vec<Vec<Span>>> {
+        None
+    }
+
+    /// The filter that code blocks of this language go through, if any.
+    fn filter(&self, lang: &str) -> Option<&'a crate::Filter> {
+        self.opts.filters.get(lang)
+    }
+
+    /// The code, after going through its language's filter or syncat, if either was requested and
+    /// it succeeded.
+    fn filtered(&self, code: &str, lang: &str, available_width: usize) -> Option<&'a str> {
+        self.outputs
+            .get(&command_block(self.opts, lang, code, available_width)?)
+    }
+
+    /// Prints the output of the filter in place of the code block, without the code block's
+    /// frame. Returns false if the filter failed, so that the code block can be printed instead.
+    fn print_replaced(&mut self, filter: &crate::Filter) -> bool {
+        let Some(code_block) = self.scope.pop() else {
+            return false;
+        };
+        let available_width = self
+            .width
+            .saturating_sub(self.prefix_len())
+            .saturating_sub(self.suffix_len());
+        let block = Block::shell(&filter.command, &self.buffer, available_width);
+        let Some(output) = self.outputs.get(&block) else {
+            self.scope.push(code_block);
+            return false;
+        };
+        let style = self.style();
+        for output_line in output.lines() {
+            let spans = ansi::parse(output_line, style, SpanKind::Content);
+            for spans in layout::wrap(spans, available_width) {
+                let width = layout::width(&spans);
+                let (prefix, _) = self.prefix();
+                let (suffix, _) = self.suffix();
+                let mut line = Line::new();
+                line.extend(prefix);
+                line.extend(spans.into_iter().map(|span| span.at(self.offset)));
+                line.push(Span::margin(
+                    " ".repeat(available_width.saturating_sub(width)),
+                    self.paper_style(),
+                ));
+                line.extend(suffix);
+                self.lines.push(line);
+            }
+        }
+        self.scope.push(code_block);
+        self.buffer.clear();
+        true
+    }
+
+    /// The code, wrapped to fit within the width.
+    fn plain_code(&self, code: &str, available_width: usize) -> String {
+        code.lines()
+            .map(|mut line| {
+                let mut output = String::new();
+                while str_width(line) > available_width {
+                    let not_too_wide = {
+                        let mut acc = 0;
+                        move |ch: &char| {
+                            acc += str_width(&ch.to_string());
+                            acc < available_width
                         }
-                    }
-                    println!(
-                        "{}{}{}{}",
-                        style.paint(" ".repeat(available_width.saturating_sub(width))),
-                        suffix,
-                        self.margin,
-                        self.shadow(),
-                    );
+                    };
+                    let prefix = line.chars().take_while(not_too_wide).collect::<String>();
+                    output = format!("{}{}\n", output, prefix);
+                    line = &line[prefix.len()..];
                 }
+                format!(
+                    "{}{}{}\n",
+                    output,
+                    line,
+                    " ".repeat(available_width.saturating_sub(str_width(line)))
+                )
+            })
+            .collect()
+    }
 
-                let (prefix, _) = first_prefix
-                    .take()
-                    .unwrap_or_else(|| self.prefix2(Some(&[&language_context[..]])));
-                let (suffix, _) = first_suffix
-                    .take()
-                    .unwrap_or_else(|| self.suffix2(Some(&[&language_context[..]])));
-                println!(
-                    "{}{}{}{}{}{}{}",
-                    self.centering,
-                    self.margin,
-                    prefix,
-                    format!(
-                        "{}{}",
-                        style.paint(" ".repeat(available_width.saturating_sub(str_width(&lang)))),
-                        self.style3(Some(&[&language_context[..]]), Some("lang-tag"))
-                            .paint(lang)
-                    ),
-                    suffix,
-                    self.margin,
-                    self.shadow(),
-                );
-            }
-            _ => {}
+    fn flush_buffer(&mut self) {
+        let Some(Scope::CodeBlock(info)) = self.scope.last() else {
+            return;
+        };
+        let filter = self.filter(language(info));
+        if let Some(filter) = filter
+            && filter.replace
+            && self.print_replaced(filter)
+        {
+            return;
         }
+        let Some(Scope::CodeBlock(info)) = self.scope.last() else {
+            return;
+        };
+        let lang = language(info);
+        let highlighted = filter.is_some()
+            || self.opts.syncat
+            || cfg!(feature = "highlight") && self.opts.highlight;
+        let language_context = if lang.is_empty() || !highlighted {
+            String::from("txt")
+        } else {
+            lang.to_owned()
+        };
+        let style = self.style3(Some(&[&language_context[..]]), None);
+        let lang = lang.to_owned();
+        let mut first_prefix = Some(self.prefix2(Some(&[&language_context[..]])));
+        let mut first_suffix = Some(self.suffix2(Some(&[&language_context[..]])));
+
+        let available_width = self
+            .width
+            .saturating_sub(first_prefix.as_ref().unwrap().1)
+            .saturating_sub(first_suffix.as_ref().unwrap().1);
+        let buffer = std::mem::take(&mut self.buffer);
+        let code_lines: Vec<Vec<Span>> =
+            if let Some(output) = self.filtered(&buffer, &lang, available_width) {
+                output
+                    .lines()
+                    .flat_map(|line| {
+                        layout::wrap(ansi::parse(line, style, SpanKind::Content), available_width)
+                    })
+                    .collect()
+            } else if let Some(lines) = self.highlight(&buffer, &lang, &language_context, style) {
+                lines
+                    .into_iter()
+                    .flat_map(|line| layout::wrap(line, available_width))
+                    .collect()
+            } else {
+                self.plain_code(&buffer, available_width)
+                    .lines()
+                    .map(|line| ansi::parse(line, style, SpanKind::Content))
+                    .collect()
+            };
+        let (prefix, _) = first_prefix
+            .take()
+            .unwrap_or_else(|| self.prefix2(Some(&[&language_context[..]])));
+        let (suffix, _) = first_suffix
+            .take()
+            .unwrap_or_else(|| self.suffix2(Some(&[&language_context[..]])));
+        let mut line = Line::new();
+        line.extend(prefix);
+        line.push(Span::content(" ".repeat(available_width), style));
+        line.extend(suffix);
+        self.lines.push(line);
+
+        for code_line in code_lines {
+            let width = layout::width(&code_line);
+            let (prefix, _) = self.prefix2(Some(&[&language_context[..]]));
+            let (suffix, _) = self.suffix2(Some(&[&language_context[..]]));
+            let mut line = Line::new();
+            line.extend(prefix);
+            line.extend(code_line.into_iter().map(|span| span.at(self.offset)));
+            line.push(Span::content(
+                " ".repeat(available_width.saturating_sub(width)),
+                style,
+            ));
+            line.extend(suffix);
+            self.lines.push(line);
+        }
+
+        let (prefix, _) = first_prefix
+            .take()
+            .unwrap_or_else(|| self.prefix2(Some(&[&language_context[..]])));
+        let (suffix, _) = first_suffix
+            .take()
+            .unwrap_or_else(|| self.suffix2(Some(&[&language_context[..]])));
+        let mut line = Line::new();
+        line.extend(prefix);
+        line.push(Span::content(
+            " ".repeat(available_width.saturating_sub(str_width(&lang))),
+            style,
+        ));
+        line.push(Span::prefix(
+            lang,
+            self.style3(Some(&[&language_context[..]]), Some("lang-tag")),
+        ));
+        line.extend(suffix);
+        self.lines.push(line);
     }
 
     fn flush(&mut self) {
@@ -498,14 +826,7 @@ impl<'a> Printer<'a> {
         if self
             .scope
             .iter()
-            .find(|scope| {
-                if let Scope::Table(..) = scope {
-                    true
-                } else {
-                    false
-                }
-            })
-            .is_some()
+            .any(|scope| matches!(scope, Scope::Table(..)))
         {
             return;
         }
@@ -514,48 +835,68 @@ impl<'a> Printer<'a> {
         }
         let (prefix, prefix_len) = self.prefix();
         let (suffix, suffix_len) = self.suffix();
-        println!(
-            "{}{}{}{}{}{}{}{}",
-            self.centering,
-            self.margin,
-            prefix,
-            self.content,
-            suffix,
-            self.paper_style().paint(
-                " ".repeat(
-                    self.width
-                        .saturating_sub(str_width(&self.content))
-                        .saturating_sub(prefix_len)
-                        .saturating_sub(suffix_len)
-                )
+        let content = std::mem::take(&mut self.content);
+        let content_len = layout::width(&content);
+        let mut line = Line::new();
+        line.extend(prefix);
+        line.extend(content);
+        line.extend(suffix);
+        line.push(Span::margin(
+            " ".repeat(
+                self.width
+                    .saturating_sub(content_len)
+                    .saturating_sub(prefix_len)
+                    .saturating_sub(suffix_len),
             ),
-            self.margin,
-            self.shadow(),
-        );
-        self.content.clear();
+            self.paper_style(),
+        ));
+        self.lines.push(line);
     }
 
-    fn target(&mut self) -> &mut String {
-        if self
-            .scope
-            .iter()
-            .find(|scope| *scope == &Scope::TableHead)
-            .is_some()
-        {
-            self.table.0.last_mut().unwrap()
-        } else if self
-            .scope
-            .iter()
-            .find(|scope| *scope == &Scope::TableRow)
-            .is_some()
-        {
-            self.table.1.last_mut().unwrap().last_mut().unwrap()
+    fn in_table_head(&self) -> bool {
+        self.scope.contains(&Scope::TableHead)
+    }
+
+    fn in_table_row(&self) -> bool {
+        self.scope.contains(&Scope::TableRow)
+    }
+
+    fn target_is_empty(&self) -> bool {
+        if self.in_table_head() {
+            self.table.0.last().unwrap().is_empty()
+        } else if self.in_table_row() {
+            self.table.1.last().unwrap().last().unwrap().is_empty()
+        } else {
+            self.content.is_empty()
+        }
+    }
+
+    fn push_target(&mut self, text: &str, style: Style) {
+        if self.in_table_head() {
+            self.table.0.last_mut().unwrap().push_str(text);
+        } else if self.in_table_row() {
+            self.table
+                .1
+                .last_mut()
+                .unwrap()
+                .last_mut()
+                .unwrap()
+                .push_str(text);
         } else {
-            &mut self.content
+            self.content
+                .push(Span::content(text, style).at(self.offset));
         }
     }
 
     fn handle_text<S>(&mut self, text: S)
+    where
+        S: AsRef<str>,
+    {
+        let style = self.style();
+        self.handle_styled_text(text, style);
+    }
+
+    fn handle_styled_text<S>(&mut self, text: S, style: Style)
     where
         S: AsRef<str>,
     {
@@ -564,14 +905,13 @@ impl<'a> Printer<'a> {
             self.buffer += s;
             return;
         }
-        let style = self.style();
         for word in Words::new(s) {
-            if str_width(&self.content) + word.len() + self.prefix_len() + self.suffix_len()
+            if layout::width(&self.content) + word.len() + self.prefix_len() + self.suffix_len()
                 > self.width
             {
                 self.flush();
             }
-            let mut word = if self.target().is_empty() {
+            let mut word = if self.target_is_empty() {
                 word.trim()
             } else {
                 &word
@@ -580,17 +920,64 @@ impl<'a> Printer<'a> {
                 .width
                 .saturating_sub(self.prefix_len())
                 .saturating_sub(self.suffix_len());
-            while str_width(&self.content) + str_width(&word) > available_len {
+            while layout::width(&self.content) + str_width(word) > available_len {
                 let part = word.chars().take(available_len).collect::<String>();
-                self.target().push_str(&format!("{}", style.paint(&part)));
+                self.push_target(&part, style);
                 word = &word[part.len()..];
                 self.flush();
             }
-            self.target().push_str(&format!("{}", style.paint(word)));
+            self.push_target(word, style);
+        }
+    }
+
+    fn start_heading(&mut self, level: HeadingLevel) {
+        self.scope.push(Scope::Heading(level));
+        let number = self
+            .numbers
+            .as_mut()
+            .and_then(|numbers| numbers.next(level as u8));
+        self.headings.push(Heading {
+            level: level as u8,
+            text: String::new(),
+            line: self.lines.len(),
+        });
+        if let Some(number) = number {
+            self.handle_styled_text(&number, self.style2(Some("number")));
+            self.handle_text(" ");
+            self.heading_text(&format!("{} ", number));
         }
     }
 
-    pub fn handle(&mut self, event: Event) {
+    fn heading_text(&mut self, text: &str) {
+        if self
+            .scope
+            .iter()
+            .any(|scope| matches!(scope, Scope::Heading(..)))
+            && let Some(heading) = self.headings.last_mut()
+        {
+            heading.text.push_str(text);
+        }
+    }
+
+    fn handle_alert(&mut self, kind: &str, icon: &str, title: &str) {
+        let style = layout::overlay(
+            self.style(),
+            resolve_scopes(self.stylesheet, &[kind], Some("prefix")),
+        );
+        self.handle_styled_text(icon, style);
+        self.handle_text(" ");
+        self.handle_styled_text(title, style);
+    }
+
+    pub fn handle(&mut self, event: Event, offset: usize) {
+        self.offset = Some(offset);
+        if let Event::Start(Tag::Heading { level, .. }) = event
+            && self.is_spanning_heading(level)
+        {
+            self.flush();
+            self.balance_columns();
+            self.width = self.full_width;
+        }
         match event {
             Event::Start(tag) => {
                 if self.empty_queued {
@@ -609,11 +996,11 @@ impl<'a> Printer<'a> {
                     } => {
                         self.flush();
                         self.print_rule();
-                        self.scope.push(Scope::Heading(HeadingLevel::H1));
+                        self.start_heading(HeadingLevel::H1);
                     }
                     Tag::Heading { level, .. } => {
                         self.flush();
-                        self.scope.push(Scope::Heading(level));
+                        self.start_heading(level);
                     }
                     Tag::BlockQuote(kind) => {
                         self.flush();
@@ -621,64 +1008,19 @@ impl<'a> Printer<'a> {
                         match kind {
                             None => {}
                             Some(BlockQuoteKind::Note) => {
-                                let style = Self::resolve_scopes(
-                                    &self.stylesheet,
-                                    &["note-blockquote"],
-                                    Some("prefix"),
-                                );
-                                self.handle_text(&format!(
-                                    "{} {}",
-                                    style.paint("󰋽"),
-                                    style.paint("Note")
-                                ));
+                                self.handle_alert("note-blockquote", "󰋽", "Note");
                             }
                             Some(BlockQuoteKind::Tip) => {
-                                let style = Self::resolve_scopes(
-                                    &self.stylesheet,
-                                    &["tip-blockquote"],
-                                    Some("prefix"),
-                                );
-                                self.handle_text(&format!(
-                                    "{} {}",
-                                    style.paint("󰌶"),
-                                    style.paint("Tip")
-                                ));
+                                self.handle_alert("tip-blockquote", "󰌶", "Tip");
                             }
                             Some(BlockQuoteKind::Important) => {
-                                let style = Self::resolve_scopes(
-                                    &self.stylesheet,
-                                    &["important-blockquote"],
-                                    Some("prefix"),
-                                );
-                                self.handle_text(&format!(
-                                    "{} {}",
-                                    style.paint("󱋉"),
-                                    style.paint("Important")
-                                ));
+                                self.handle_alert("important-blockquote", "󱋉", "Important");
                             }
                             Some(BlockQuoteKind::Warning) => {
-                                let style = Self::resolve_scopes(
-                                    &self.stylesheet,
-                                    &["warning-blockquote"],
-                                    Some("prefix"),
-                                );
-                                self.handle_text(&format!(
-                                    "{} {}",
-                                    style.paint("󰀪"),
-                                    style.paint("Warning")
-                                ));
+                                self.handle_alert("warning-blockquote", "󰀪", "Warning");
                             }
                             Some(BlockQuoteKind::Caution) => {
-                                let style = Self::resolve_scopes(
-                                    &self.stylesheet,
-                                    &["caution-blockquote"],
-                                    Some("prefix"),
-                                );
-                                self.handle_text(&format!(
-                                    "{} {}",
-                                    style.paint("󰳦"),
-                                    style.paint("Caution")
-                                ));
+                                self.handle_alert("caution-blockquote", "󰳦", "Caution");
                             }
                         }
                     }
@@ -717,7 +1059,7 @@ impl<'a> Printer<'a> {
                     Tag::FootnoteDefinition(text) => {
                         self.flush();
                         self.scope.push(Scope::FootnoteDefinition);
-                        self.handle_text(&format!("{}:", text));
+                        self.handle_text(format!("{}:", text));
                         self.scope.pop();
                         self.flush();
                         self.scope.push(Scope::FootnoteContent);
@@ -732,12 +1074,7 @@ impl<'a> Printer<'a> {
                     }
                     Tag::TableCell => {
                         self.scope.push(Scope::TableCell);
-                        if self
-                            .scope
-                            .iter()
-                            .find(|scope| *scope == &Scope::TableHead)
-                            .is_some()
-                        {
+                        if self.in_table_head() {
                             self.table.0.push(String::new());
                         } else {
                             self.table.1.last_mut().unwrap().push(String::new());
@@ -765,36 +1102,65 @@ impl<'a> Printer<'a> {
                     } => {
                         self.flush();
 
-                        if !self.opts.no_images {
+                        if !self.opts.no_images && self.opts.color {
                             let available_width = self
                                 .width
                                 .saturating_sub(self.prefix_len())
                                 .saturating_sub(self.suffix_len());
-                            match image::open(dest_url.as_ref()) {
+                            let base = self.opts.path.as_deref().and_then(Path::parent);
+                            match images::open(&dest_url, base) {
                                 Ok(image) => {
-                                    let (mut width, mut height) = image.dimensions();
-                                    if width > available_width as u32 {
-                                        let scale = available_width as f64 / width as f64;
-                                        width = (width as f64 * scale) as u32;
-                                        height = (height as f64 * scale) as u32;
-                                    }
+                                    let (width, height) =
+                                        self.image_size(image.dimensions(), available_width);
                                     let mut vec = vec![];
-                                    termpix::print_image(image, true, width, height, &mut vec);
+                                    termpix::print_image(
+                                        &image.rasterize(width, height),
+                                        self.opts.color_depth,
+                                        self.opts.dither,
+                                        self.opts.image_filter,
+                                        width,
+                                        height,
+                                        &mut vec,
+                                    );
                                     let string = String::from_utf8(vec).unwrap();
+                                    // Graphics protocols and exports draw the image at the
+                                    // resolution of the cells, rather than of the half blocks.
+                                    let (cell_width, cell_height) = self.opts.cell_size;
+                                    let image =
+                                        Arc::new(image.into_raster(
+                                            width * cell_width,
+                                            height / 2 * cell_height,
+                                        ));
+                                    let rows = string.lines().count();
 
-                                    for line in string.lines() {
+                                    for (row, image_line) in string.lines().enumerate() {
                                         let (prefix, _) = self.prefix();
                                         let (suffix, _) = self.suffix();
-                                        println!(
-                                            "{}{}{}{}{}{}{}",
-                                            self.centering,
-                                            self.margin,
-                                            prefix,
-                                            line,
-                                            suffix,
-                                            self.margin,
-                                            self.shadow(),
+                                        let mut pixels = ansi::parse(
+                                            image_line,
+                                            self.paper_style(),
+                                            SpanKind::Image,
+                                        );
+                                        if let Some(first) = pixels.first_mut() {
+                                            first.picture = Some(Picture {
+                                                image: image.clone(),
+                                                row,
+                                                rows,
+                                            });
+                                        }
+                                        let padding =
+                                            available_width.saturating_sub(layout::width(&pixels));
+                                        let mut line = Line::new();
+                                        line.extend(prefix);
+                                        line.extend(
+                                            pixels.into_iter().map(|span| span.at(self.offset)),
                                         );
+                                        line.push(Span::margin(
+                                            " ".repeat(padding),
+                                            self.paper_style(),
+                                        ));
+                                        line.extend(suffix);
+                                        self.lines.push(line);
                                     }
 
                                     self.scope.push(Scope::Indent);
@@ -810,7 +1176,7 @@ impl<'a> Printer<'a> {
                                     });
                                     self.handle_text(dest_url);
                                     self.scope.pop();
-                                    self.handle_text(&format!(": {}", error));
+                                    self.handle_text(format!(": {}", error));
                                     self.scope.push(Scope::Caption);
                                     self.flush();
                                 }
@@ -851,7 +1217,13 @@ impl<'a> Printer<'a> {
                     self.flush();
                     self.scope.pop();
                     self.print_rule();
-                    self.queue_empty();
+                    if self.width == self.full_width && self.in_columns() {
+                        self.empty();
+                        self.width = self.column_width;
+                        self.flow_start = self.lines.len();
+                    } else {
+                        self.queue_empty();
+                    }
                 }
                 TagEnd::Heading(_) => {
                     self.flush();
@@ -932,9 +1304,11 @@ impl<'a> Printer<'a> {
                 self.print_rule();
             }
             Event::Text(text) => {
+                self.heading_text(&text);
                 self.handle_text(text);
             }
             Event::Code(text) => {
+                self.heading_text(&text);
                 self.scope.push(Scope::Code);
                 self.handle_text(text);
                 self.scope.pop();
@@ -948,7 +1322,7 @@ impl<'a> Printer<'a> {
             }
             Event::FootnoteReference(text) => {
                 self.scope.push(Scope::FootnoteReference);
-                self.handle_text(&format!("[{}]", text));
+                self.handle_text(format!("[{}]", text));
                 self.scope.pop();
             }
             Event::SoftBreak => {
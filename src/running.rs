@@ -0,0 +1,107 @@
+//! Running headers and footers, written into the margins of each sheet of paper.
+
+use crate::layout::Heading;
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Text to write in the left, centre, and right of a margin. The text may include placeholders:
+///
+/// *   `{file}`: the name of the file being printed
+/// *   `{heading}`: the current top-level heading
+/// *   `{page}`: the current page number
+/// *   `{pages}`: the total number of pages
+/// *   `{date}`: today's date
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MarginText {
+    pub left: String,
+    pub center: String,
+    pub right: String,
+}
+
+impl MarginText {
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty() && self.center.is_empty() && self.right.is_empty()
+    }
+}
+
+/// Parses margin text from a template of the form `left|center|right`. With only one part, it is
+/// centred; with two parts, they are placed on the left and right.
+impl FromStr for MarginText {
+    type Err = Infallible;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let parts = template.splitn(3, '|').collect::<Vec<_>>();
+        let (left, center, right) = match parts[..] {
+            [center] => ("", center, ""),
+            [left, right] => (left, "", right),
+            [left, center, right] => (left, center, right),
+            _ => unreachable!(),
+        };
+        Ok(MarginText {
+            left: left.to_owned(),
+            center: center.to_owned(),
+            right: right.to_owned(),
+        })
+    }
+}
+
+/// The values that placeholders are replaced with on a particular page.
+pub(crate) struct Placeholders<'a> {
+    pub file: &'a str,
+    pub heading: &'a str,
+    pub page: usize,
+    pub pages: usize,
+    pub date: &'a str,
+}
+
+impl Placeholders<'_> {
+    pub fn expand(&self, margin_text: &MarginText) -> MarginText {
+        MarginText {
+            left: self.expand_str(&margin_text.left),
+            center: self.expand_str(&margin_text.center),
+            right: self.expand_str(&margin_text.right),
+        }
+    }
+
+    fn expand_str(&self, template: &str) -> String {
+        template
+            .replace("{file}", self.file)
+            .replace("{heading}", self.heading)
+            .replace("{pages}", &self.pages.to_string())
+            .replace("{page}", &self.page.to_string())
+            .replace("{date}", self.date)
+    }
+}
+
+/// The top-level heading in effect at the end of the lines up to `end`.
+pub(crate) fn current_heading(headings: &[Heading], end: usize) -> &str {
+    let Some(top_level) = headings.iter().map(|heading| heading.level).min() else {
+        return "";
+    };
+    headings
+        .iter()
+        .rfind(|heading| heading.level == top_level && heading.line < end)
+        .map(|heading| heading.text.as_str())
+        .unwrap_or("")
+}
+
+/// Today's date (in UTC), formatted as `YYYY-MM-DD`.
+pub(crate) fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+    // Converts days since the epoch to a civil date: http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
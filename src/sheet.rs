@@ -0,0 +1,174 @@
+//! Arranges the lines of a document's body onto sheets of paper, surrounded by margins and
+//! casting a shadow.
+
+use crate::layout::{Line, Span};
+use crate::printer::resolve_scopes;
+use crate::running::MarginText;
+use crate::str_width::{str_width, truncate};
+use ansi_term::Style;
+use syncat_stylesheet::Stylesheet;
+
+/// The lines of the body that go on one sheet of paper, along with the text of its margins.
+pub struct Page {
+    pub body: Vec<Line>,
+    pub header: Option<Line>,
+    pub footer: Option<Line>,
+}
+
+pub struct Sheet {
+    width: usize,
+    h_margin: usize,
+    top_margin: usize,
+    bottom_margin: usize,
+    paper_style: Style,
+    shadow_style: Style,
+}
+
+impl Sheet {
+    pub fn new(
+        stylesheet: &Stylesheet,
+        width: usize,
+        h_margin: usize,
+        top_margin: usize,
+        bottom_margin: usize,
+    ) -> Self {
+        Self {
+            width,
+            h_margin,
+            top_margin,
+            bottom_margin,
+            paper_style: resolve_scopes(stylesheet, &["paper"], None),
+            shadow_style: resolve_scopes(stylesheet, &["shadow"], None),
+        }
+    }
+
+    /// The number of body lines that fit on a sheet of the given height, or `None` if the sheet is
+    /// too short to fit any.
+    pub fn body_height(&self, page_height: usize) -> Option<usize> {
+        page_height
+            .checked_sub(self.top_margin + self.bottom_margin + 1)
+            .filter(|height| *height > 0)
+    }
+
+    /// Splits the body into pages of the given height, if any.
+    pub fn paginate(&self, mut body: Vec<Line>, page_height: Option<usize>) -> Vec<Vec<Line>> {
+        let Some(body_height) = page_height.and_then(|height| self.body_height(height)) else {
+            return vec![body];
+        };
+        let mut pages = vec![];
+        while body.len() > body_height {
+            let rest = body.split_off(body_height);
+            pages.push(std::mem::replace(&mut body, rest));
+        }
+        while body.len() < body_height {
+            body.push(self.blank_body_line());
+        }
+        pages.push(body);
+        pages
+    }
+
+    /// Draws a sheet of paper around each of the pages.
+    pub fn frame(&self, pages: Vec<Page>) -> Vec<Vec<Line>> {
+        pages
+            .into_iter()
+            .map(|page| self.frame_page(page))
+            .collect()
+    }
+
+    fn frame_page(&self, page: Page) -> Vec<Line> {
+        let Page {
+            body,
+            mut header,
+            mut footer,
+        } = page;
+        let mut lines = vec![];
+        lines.push(Line::from_iter([self.blank()]));
+        for row in 0..self.top_margin {
+            let mut line = match header.take_if(|_| row == self.top_margin / 2) {
+                Some(header) => header,
+                None => Line::from_iter([self.blank()]),
+            };
+            line.push(self.shadow());
+            lines.push(line);
+        }
+        for body_line in body {
+            let mut line = Line::from_iter([self.margin()]);
+            line.extend(body_line.spans);
+            line.extend([self.margin(), self.shadow()]);
+            lines.push(line);
+        }
+        for row in 0..self.bottom_margin {
+            let mut line = match footer.take_if(|_| row == self.bottom_margin / 2) {
+                Some(footer) => footer,
+                None => Line::from_iter([self.blank()]),
+            };
+            line.push(self.shadow());
+            lines.push(line);
+        }
+        lines.push(Line::from_iter([
+            Span::margin(" ", Style::default()),
+            Span::margin(" ".repeat(self.width), self.shadow_style),
+        ]));
+        lines
+    }
+
+    /// A line of the top or bottom margin, with text written at the left, centre, and right. The
+    /// left and right text line up with the edges of the body. Text that does not fit is cut off.
+    pub fn margin_line(&self, text: &MarginText, style: Style) -> Line {
+        let inner_width = self.width - 2 * self.h_margin;
+        let right = truncate(&text.right, inner_width);
+        let right_width = str_width(&right);
+        let left = truncate(&text.left, inner_width - right_width);
+        let left_width = str_width(&left);
+
+        let center_width = str_width(&text.center);
+        let center_start = self.width.saturating_sub(center_width) / 2;
+        let center = if center_start >= self.h_margin + left_width
+            && center_start + center_width + right_width + self.h_margin <= self.width
+        {
+            text.center.as_str()
+        } else {
+            ""
+        };
+
+        let right_start = self.width - self.h_margin - right_width;
+        let mut line = Line::new();
+        let mut column = 0;
+        for (start, text) in [
+            (self.h_margin, left.as_str()),
+            (center_start, center),
+            (right_start, right.as_str()),
+        ] {
+            if text.is_empty() {
+                continue;
+            }
+            line.push(Span::margin(" ".repeat(start - column), self.paper_style));
+            line.push(Span::content(text, style));
+            column = start + str_width(text);
+        }
+        line.push(Span::margin(
+            " ".repeat(self.width - column),
+            self.paper_style,
+        ));
+        line
+    }
+
+    fn blank_body_line(&self) -> Line {
+        Line::from_iter([Span::margin(
+            " ".repeat(self.width - 2 * self.h_margin),
+            self.paper_style,
+        )])
+    }
+
+    fn blank(&self) -> Span {
+        Span::margin(" ".repeat(self.width), self.paper_style)
+    }
+
+    fn margin(&self) -> Span {
+        Span::margin(" ".repeat(self.h_margin), self.paper_style)
+    }
+
+    fn shadow(&self) -> Span {
+        Span::margin(" ", self.shadow_style)
+    }
+}
@@ -13,3 +13,14 @@ pub fn str_width(s: &str) -> usize {
         })
         .sum()
 }
+
+/// Cuts off the end of the text, so that it fits within the width.
+pub fn truncate(text: &str, width: usize) -> String {
+    let mut used = 0;
+    text.chars()
+        .take_while(|ch| {
+            used += str_width(&ch.to_string());
+            used <= width
+        })
+        .collect()
+}
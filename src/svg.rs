@@ -0,0 +1,167 @@
+//! Writes laid out lines as an SVG image, a vector screenshot of the paper. Each line is a row of a
+//! character grid: backgrounds are drawn as rects, and text on top of them. The pixels of images
+//! are drawn as rects too, two to each cell.
+
+use crate::html::{escape, text_decoration};
+use crate::layout::{Line, SpanKind};
+use crate::palette::{self, BACKGROUND, FOREGROUND};
+use ansi_term::Style;
+use std::io::{self, Write};
+
+const FONT_SIZE: usize = 15;
+/// The size of each cell of the grid. Cells are twice as tall as they are wide, like in the
+/// terminal, so that the pixels of images are square.
+const CELL_WIDTH: usize = 9;
+const CELL_HEIGHT: usize = 18;
+/// Where the baseline of the text is within each cell.
+const BASELINE: usize = 14;
+
+/// A rectangle of colour, which grows to cover the cells to its right that are the same colour.
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    colour: [u8; 3],
+}
+
+#[derive(Default)]
+struct Rects(Vec<Rect>);
+
+impl Rects {
+    fn push(&mut self, x: usize, y: usize, width: usize, height: usize, colour: [u8; 3]) {
+        if let Some(last) = self.0.last_mut()
+            && last.y == y
+            && last.height == height
+            && last.x + last.width == x
+            && last.colour == colour
+        {
+            last.width += width;
+            return;
+        }
+        self.0.push(Rect {
+            x,
+            y,
+            width,
+            height,
+            colour,
+        });
+    }
+}
+
+/// Writes the lines as an SVG image with the given title.
+pub fn write_document<W: Write>(lines: &[Line], title: &str, out: &mut W) -> io::Result<()> {
+    let columns = lines.iter().map(Line::width).max().unwrap_or(0);
+    let width = columns * CELL_WIDTH;
+    let height = lines.len() * CELL_HEIGHT;
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\" font-family=\"ui-monospace, Menlo, Consolas, monospace\" font-size=\"{FONT_SIZE}\">",
+    )?;
+    writeln!(out, "<title>{}</title>", escape(title))?;
+    writeln!(
+        out,
+        "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+        palette::hex(palette::rgb(BACKGROUND))
+    )?;
+
+    let mut rects = Rects::default();
+    // The lower halves of the pixels are kept apart from the upper halves, so that each can grow
+    // across the row.
+    let mut lower_halves = Rects::default();
+    let mut texts: Vec<Text> = vec![];
+    for (row, line) in lines.iter().enumerate() {
+        let y = row * CELL_HEIGHT;
+        let mut column = 0;
+        for span in &line.spans {
+            let x = column * CELL_WIDTH;
+            let span_width = span.width();
+            column += span_width;
+            if span.kind == SpanKind::Image {
+                let top = span.style.background.unwrap_or(BACKGROUND);
+                let bottom = span.style.foreground.unwrap_or(BACKGROUND);
+                let half = CELL_HEIGHT / 2;
+                for cell in 0..span_width {
+                    let x = x + cell * CELL_WIDTH;
+                    rects.push(x, y, CELL_WIDTH, half, palette::rgb(top));
+                    lower_halves.push(x, y + half, CELL_WIDTH, half, palette::rgb(bottom));
+                }
+                continue;
+            }
+            let (_, background) = palette::colours(span.style);
+            if let Some(background) = background {
+                rects.push(x, y, span_width * CELL_WIDTH, CELL_HEIGHT, background);
+            }
+            match texts.last_mut() {
+                Some(text)
+                    if text.row == row
+                        && text.end == column - span_width
+                        && text.style == span.style =>
+                {
+                    text.text.push_str(&span.text);
+                    text.end = column;
+                }
+                _ => texts.push(Text {
+                    row,
+                    start: column - span_width,
+                    end: column,
+                    style: span.style,
+                    text: span.text.clone(),
+                }),
+            }
+        }
+    }
+
+    writeln!(out, "<g shape-rendering=\"crispEdges\">")?;
+    for rect in rects.0.into_iter().chain(lower_halves.0) {
+        writeln!(
+            out,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            rect.x,
+            rect.y,
+            rect.width,
+            rect.height,
+            palette::hex(rect.colour),
+        )?;
+    }
+    writeln!(out, "</g>")?;
+    writeln!(out, "<g xml:space=\"preserve\" style=\"white-space: pre\">")?;
+    for text in texts {
+        if !text.text.trim().is_empty() {
+            write_text(&text, out)?;
+        }
+    }
+    writeln!(out, "</g>")?;
+    writeln!(out, "</svg>")
+}
+
+/// A run of text in the same style.
+struct Text {
+    row: usize,
+    start: usize,
+    end: usize,
+    style: Style,
+    text: String,
+}
+
+fn write_text<W: Write>(text: &Text, out: &mut W) -> io::Result<()> {
+    let (foreground, _) = palette::colours(text.style);
+    write!(
+        out,
+        "<text x=\"{}\" y=\"{}\" textLength=\"{}\" lengthAdjust=\"spacingAndGlyphs\" fill=\"{}\"",
+        text.start * CELL_WIDTH,
+        text.row * CELL_HEIGHT + BASELINE,
+        (text.end - text.start) * CELL_WIDTH,
+        palette::hex(foreground.unwrap_or(palette::rgb(FOREGROUND))),
+    )?;
+    if text.style.is_bold {
+        write!(out, " font-weight=\"bold\"")?;
+    }
+    if text.style.is_italic {
+        write!(out, " font-style=\"italic\"")?;
+    }
+    if let Some(decoration) = text_decoration(text.style) {
+        write!(out, " text-decoration=\"{}\"", decoration)?;
+    }
+    writeln!(out, ">{}</text>", escape(&text.text))
+}
@@ -1,9 +1,8 @@
 use crate::str_width;
 use crate::words::Words;
-use ansi_term::Style;
 use console::strip_ansi_codes;
 use pulldown_cmark::Alignment;
-use std::io::Write;
+use std::fmt::Write;
 
 pub struct Table {
     titles: Vec<String>,
@@ -20,7 +19,7 @@ impl Table {
         }
     }
 
-    pub fn print(self, paper_style: Style, alignment: &[Alignment]) -> String {
+    pub fn print(self, alignment: &[Alignment]) -> Vec<String> {
         let Table {
             titles,
             rows,
@@ -111,65 +110,49 @@ impl Table {
                 .collect()
         };
         if col_widths.iter().sum::<usize>() > max_chars_width {
-            return format!("{}", paper_style.paint("[Table too large to fit]"));
+            return vec![String::from("[Table too large to fit]")];
         }
 
-        let mut buffer = vec![];
-        print_separator(&mut buffer, &col_widths, '─', '┌', '┬', '┐', paper_style);
+        let mut buffer = String::new();
+        print_separator(&mut buffer, &col_widths, '─', '┌', '┬', '┐');
         if !titles.is_empty() {
-            print_row(&mut buffer, &col_widths, alignment, &titles, paper_style);
-            print_separator(&mut buffer, &col_widths, '═', '╞', '╪', '╡', paper_style);
+            print_row(&mut buffer, &col_widths, alignment, &titles);
+            print_separator(&mut buffer, &col_widths, '═', '╞', '╪', '╡');
         }
         let row_count = rows.len();
         for (i, row) in rows.into_iter().enumerate() {
-            print_row(&mut buffer, &col_widths, alignment, &row, paper_style);
+            print_row(&mut buffer, &col_widths, alignment, &row);
             if i != row_count - 1 {
-                print_separator(&mut buffer, &col_widths, '─', '├', '┼', '┤', paper_style);
+                print_separator(&mut buffer, &col_widths, '─', '├', '┼', '┤');
             }
         }
-        print_separator(&mut buffer, &col_widths, '─', '└', '┴', '┘', paper_style);
+        print_separator(&mut buffer, &col_widths, '─', '└', '┴', '┘');
 
-        String::from_utf8(buffer).unwrap()
+        buffer.lines().map(str::to_owned).collect()
     }
 }
 
-fn print_row<W: Write>(
-    w: &mut W,
-    cols: &[usize],
-    alignment: &[Alignment],
-    row: &[String],
-    paper_style: Style,
-) {
-    let mut row_words = row.into_iter().map(|s| Words::new(s)).collect::<Vec<_>>();
+fn print_row<W: Write>(w: &mut W, cols: &[usize], alignment: &[Alignment], row: &[String]) {
+    let mut row_words = row.iter().map(Words::new).collect::<Vec<_>>();
     loop {
         let mut done = true;
-        write!(w, "{}", paper_style.paint("│")).unwrap();
+        write!(w, "│").unwrap();
         for (i, words) in row_words.iter_mut().enumerate() {
             let mut line = match words.next() {
                 Some(line) => line.trim().to_string(),
                 None => {
-                    write!(
-                        w,
-                        "{}",
-                        paper_style.paint(format!(" {: <width$} │", " ", width = cols[i]))
-                    )
-                    .unwrap();
+                    write!(w, " {: <width$} │", " ", width = cols[i]).unwrap();
                     continue;
                 }
             };
-            loop {
-                match words.next() {
-                    Some(next) => {
-                        if str_width(&line) + str_width(&next) <= cols[i] {
-                            line += &next;
-                        } else {
-                            words.undo();
-                            done = false;
-                            break;
-                        }
-                    }
-                    None => break,
-                };
+            while let Some(next) = words.next() {
+                if str_width(&line) + str_width(&next) <= cols[i] {
+                    line += &next;
+                } else {
+                    words.undo();
+                    done = false;
+                    break;
+                }
             }
             line = line.trim().to_string();
             let padded = if alignment[i] == Alignment::Center {
@@ -191,9 +174,9 @@ fn print_row<W: Write>(
                     width = cols[i] - (line.len().saturating_sub(str_width(&line)))
                 )
             };
-            write!(w, "{}", paper_style.paint(padded)).unwrap();
+            write!(w, "{}", padded).unwrap();
         }
-        write!(w, "\n").unwrap();
+        writeln!(w).unwrap();
         if done {
             break;
         }
@@ -207,17 +190,11 @@ fn print_separator<W: Write>(
     left: char,
     cross: char,
     right: char,
-    paper_style: Style,
 ) {
     let line = cols
         .iter()
         .map(|width| mid.to_string().repeat(*width))
         .collect::<Vec<_>>()
         .join(&format!("{}{}{}", mid, cross, mid));
-    write!(
-        w,
-        "{}\n",
-        paper_style.paint(format!("{}{}{}{}{}", left, mid, line, mid, right))
-    )
-    .unwrap();
+    writeln!(w, "{}{}{}{}{}", left, mid, line, mid, right).unwrap();
 }
@@ -1,55 +1,72 @@
 //! This module is being used temporarily until someone publishes termpix to crates.io
+use crate::palette::{self, ANSI_COLOURS, ColorDepth};
 use ansi_term::ANSIStrings;
 use ansi_term::Colour::Fixed;
 use image::{
-    Pixel,
+    ImageBuffer, Luma, Rgb, Rgb32FImage, RgbImage, Rgba,
     imageops::{self, FilterType},
 };
 use std::io::Write;
 
+/// How to spread the error of rounding each pixel to the nearest colour of the palette, so that
+/// gradients don't turn into bands.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Each pixel is rounded on its own.
+    #[default]
+    None,
+    /// The error of each pixel is passed on to the pixels after it.
+    FloydSteinberg,
+    /// Each pixel is nudged by a repeating pattern before it is rounded.
+    Bayer,
+}
+
+#[rustfmt::skip]
+const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// The colour that transparent pixels are blended with.
+const TRANSPARENT: f32 = 38.0;
+
 pub fn print_image<W: Write>(
-    img: image::DynamicImage,
-    true_colour: bool,
+    img: &image::DynamicImage,
+    depth: ColorDepth,
+    dither: Dither,
+    filter: FilterType,
     width: u32,
     height: u32,
     w: &mut W,
 ) {
-    let img = imageops::resize(&img, width, height, FilterType::Nearest);
-
-    if !true_colour {
-        for y in 0..height {
-            //TODO: inc by 2 instead
-            if y % 2 == 1 || y + 1 == height {
-                continue;
-            }
+    let img = resize(img, width, height, filter);
 
+    if depth != ColorDepth::TrueColor {
+        let indices = quantize(&img, depth, dither);
+        let index = |x: u32, y: u32| indices[(y * width + x) as usize];
+        for y in (0..height).step_by(2) {
+            // An image with an odd number of rows has its last row drawn in both halves of the
+            // last line, rather than leaving it out.
+            let bottom = u32::min(y + 1, height - 1);
             let row: Vec<_> = (0..width)
-                .map(|x| {
-                    let mut top = img[(x, y)];
-                    let mut bottom = img[(x, y + 1)];
-                    blend_alpha(&mut top);
-                    blend_alpha(&mut bottom);
-                    let top_colour = find_colour_index(top.to_rgb().channels());
-                    let bottom_colour = find_colour_index(bottom.to_rgb().channels());
-                    Fixed(bottom_colour).on(Fixed(top_colour)).paint("▄")
-                })
+                .map(|x| Fixed(index(x, bottom)).on(Fixed(index(x, y))).paint("▄"))
                 .collect();
 
-            write!(w, "{}\n", ANSIStrings(&row)).ok();
+            writeln!(w, "{}", ANSIStrings(&row)).ok();
         }
     } else {
         let mut row = Vec::new();
-        for y in 0..height {
-            //TODO: inc by 2 instead
-            if y % 2 == 1 || y + 1 == height {
-                continue;
-            }
-
+        for y in (0..height).step_by(2) {
+            let bottom = u32::min(y + 1, height - 1);
             for x in 0..width {
-                let mut top = img[(x, y)];
-                let mut bottom = img[(x, y + 1)];
-                blend_alpha(&mut top);
-                blend_alpha(&mut bottom);
+                let top = img[(x, y)];
+                let bottom = img[(x, bottom)];
                 write!(
                     row,
                     "\x1b[48;2;{};{};{}m\x1b[38;2;{};{};{}m▄",
@@ -58,90 +75,111 @@ pub fn print_image<W: Write>(
                 .unwrap();
             }
 
-            write!(row, "\x1b[m\n").unwrap();
-            w.write(&row).unwrap();
+            writeln!(row, "\x1b[m").unwrap();
+            w.write_all(&row).unwrap();
             row.clear();
         }
     }
 }
 
-fn find_colour_index(pixel: &[u8]) -> u8 {
-    let mut best = 0;
-    let mut best_distance = 255 * 255 * 3 + 1;
-    for i in 16..255 {
-        let ansi_colour = ANSI_COLOURS[i];
-        let dr = ansi_colour[0] - pixel[0] as i32;
-        let dg = ansi_colour[1] - pixel[1] as i32;
-        let db = ansi_colour[2] - pixel[2] as i32;
-        let distance = dr * dr + dg * dg + db * db;
+/// Resizes the image, and blends it over the background where it is transparent. Pixels are
+/// averaged together as light, rather than as sRGB values, which would darken the edges between
+/// light and dark areas, such as thin lines in a diagram. The colours are weighted by their alpha
+/// while they are averaged, so that the colours of transparent pixels don't bleed in.
+///
+/// The image is resampled to twice as many rows as it is drawn with, and each pair of those rows is
+/// averaged into one, so that even filters that only pick pixels, like [`FilterType::Nearest`],
+/// don't skip over rows when the image is shrunk.
+fn resize(img: &image::DynamicImage, width: u32, height: u32, filter: FilterType) -> RgbImage {
+    let img = img.to_rgba8();
+    let linear = Rgb32FImage::from_fn(img.width(), img.height(), |x, y| {
+        let Rgba([r, g, b, a]) = img[(x, y)];
+        let alpha = a as f32 / 255.0;
+        Rgb([r, g, b].map(|channel| to_linear(channel) * alpha))
+    });
+    let alpha = ImageBuffer::<Luma<f32>, _>::from_fn(img.width(), img.height(), |x, y| {
+        Luma([img[(x, y)][3] as f32 / 255.0])
+    });
+    let linear = imageops::resize(&linear, width, height * 2, filter);
+    let alpha = imageops::resize(&alpha, width, height * 2, filter);
+    RgbImage::from_fn(width, height, |x, y| {
+        let (top, bottom) = ((x, y * 2), (x, y * 2 + 1));
+        let alpha = ((alpha[top][0] + alpha[bottom][0]) / 2.0).clamp(0.0, 1.0);
+        let colour = [0, 1, 2].map(|channel| {
+            let channel = (linear[top][channel] + linear[bottom][channel]) / 2.0;
+            let channel = if alpha > 0.0 { channel / alpha } else { 0.0 };
+            from_linear(channel)
+        });
+        Rgb(colour.map(|channel| blend(channel, alpha).round() as u8))
+    })
+}
 
-        if distance < best_distance {
-            best_distance = distance;
-            best = i as u8;
-        }
-    }
+fn blend(channel: f32, alpha: f32) -> f32 {
+    alpha * channel + (1.0 - alpha) * TRANSPARENT
+}
 
-    return best;
+fn to_linear(channel: u8) -> f32 {
+    let channel = channel as f32 / 255.0;
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
 }
 
-fn blend_alpha(pixel: &mut image::Rgba<u8>) {
-    let alpha = pixel[3] as i32 as f32 / 255.0;
-    pixel[0] = (alpha * (pixel[0] as i32 as f32) + (1.0 - alpha) * 38.0) as u8;
-    pixel[1] = (alpha * (pixel[1] as i32 as f32) + (1.0 - alpha) * 38.0) as u8;
-    pixel[2] = (alpha * (pixel[2] as i32 as f32) + (1.0 - alpha) * 38.0) as u8;
+fn from_linear(channel: f32) -> f32 {
+    let channel = channel.clamp(0.0, 1.0);
+    let channel = if channel <= 0.003_130_8 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    };
+    channel * 255.0
 }
 
-#[rustfmt::skip]
-static ANSI_COLOURS: [[i32; 3]; 256] = [
-[ 0x00, 0x00, 0x00 ],[ 0x80, 0x00, 0x00 ],[ 0x00, 0x80, 0x00 ],[ 0x80, 0x80, 0x00 ],[ 0x00, 0x00, 0x80 ],
-[ 0x80, 0x00, 0x80 ],[ 0x00, 0x80, 0x80 ],[ 0xc0, 0xc0, 0xc0 ],[ 0x80, 0x80, 0x80 ],[ 0xff, 0x00, 0x00 ],
-[ 0x00, 0xff, 0x00 ],[ 0xff, 0xff, 0x00 ],[ 0x00, 0x00, 0xff ],[ 0xff, 0x00, 0xff ],[ 0x00, 0xff, 0xff ],
-[ 0xff, 0xff, 0xff ],[ 0x00, 0x00, 0x00 ],[ 0x00, 0x00, 0x5f ],[ 0x00, 0x00, 0x87 ],[ 0x00, 0x00, 0xaf ],
-[ 0x00, 0x00, 0xd7 ],[ 0x00, 0x00, 0xff ],[ 0x00, 0x5f, 0x00 ],[ 0x00, 0x5f, 0x5f ],[ 0x00, 0x5f, 0x87 ],
-[ 0x00, 0x5f, 0xaf ],[ 0x00, 0x5f, 0xd7 ],[ 0x00, 0x5f, 0xff ],[ 0x00, 0x87, 0x00 ],[ 0x00, 0x87, 0x5f ],
-[ 0x00, 0x87, 0x87 ],[ 0x00, 0x87, 0xaf ],[ 0x00, 0x87, 0xd7 ],[ 0x00, 0x87, 0xff ],[ 0x00, 0xaf, 0x00 ],
-[ 0x00, 0xaf, 0x5f ],[ 0x00, 0xaf, 0x87 ],[ 0x00, 0xaf, 0xaf ],[ 0x00, 0xaf, 0xd7 ],[ 0x00, 0xaf, 0xff ],
-[ 0x00, 0xd7, 0x00 ],[ 0x00, 0xd7, 0x5f ],[ 0x00, 0xd7, 0x87 ],[ 0x00, 0xd7, 0xaf ],[ 0x00, 0xd7, 0xd7 ],
-[ 0x00, 0xd7, 0xff ],[ 0x00, 0xff, 0x00 ],[ 0x00, 0xff, 0x5f ],[ 0x00, 0xff, 0x87 ],[ 0x00, 0xff, 0xaf ],
-[ 0x00, 0xff, 0xd7 ],[ 0x00, 0xff, 0xff ],[ 0x5f, 0x00, 0x00 ],[ 0x5f, 0x00, 0x5f ],[ 0x5f, 0x00, 0x87 ],
-[ 0x5f, 0x00, 0xaf ],[ 0x5f, 0x00, 0xd7 ],[ 0x5f, 0x00, 0xff ],[ 0x5f, 0x5f, 0x00 ],[ 0x5f, 0x5f, 0x5f ],
-[ 0x5f, 0x5f, 0x87 ],[ 0x5f, 0x5f, 0xaf ],[ 0x5f, 0x5f, 0xd7 ],[ 0x5f, 0x5f, 0xff ],[ 0x5f, 0x87, 0x00 ],
-[ 0x5f, 0x87, 0x5f ],[ 0x5f, 0x87, 0x87 ],[ 0x5f, 0x87, 0xaf ],[ 0x5f, 0x87, 0xd7 ],[ 0x5f, 0x87, 0xff ],
-[ 0x5f, 0xaf, 0x00 ],[ 0x5f, 0xaf, 0x5f ],[ 0x5f, 0xaf, 0x87 ],[ 0x5f, 0xaf, 0xaf ],[ 0x5f, 0xaf, 0xd7 ],
-[ 0x5f, 0xaf, 0xff ],[ 0x5f, 0xd7, 0x00 ],[ 0x5f, 0xd7, 0x5f ],[ 0x5f, 0xd7, 0x87 ],[ 0x5f, 0xd7, 0xaf ],
-[ 0x5f, 0xd7, 0xd7 ],[ 0x5f, 0xd7, 0xff ],[ 0x5f, 0xff, 0x00 ],[ 0x5f, 0xff, 0x5f ],[ 0x5f, 0xff, 0x87 ],
-[ 0x5f, 0xff, 0xaf ],[ 0x5f, 0xff, 0xd7 ],[ 0x5f, 0xff, 0xff ],[ 0x87, 0x00, 0x00 ],[ 0x87, 0x00, 0x5f ],
-[ 0x87, 0x00, 0x87 ],[ 0x87, 0x00, 0xaf ],[ 0x87, 0x00, 0xd7 ],[ 0x87, 0x00, 0xff ],[ 0x87, 0x5f, 0x00 ],
-[ 0x87, 0x5f, 0x5f ],[ 0x87, 0x5f, 0x87 ],[ 0x87, 0x5f, 0xaf ],[ 0x87, 0x5f, 0xd7 ],[ 0x87, 0x5f, 0xff ],
-[ 0x87, 0x87, 0x00 ],[ 0x87, 0x87, 0x5f ],[ 0x87, 0x87, 0x87 ],[ 0x87, 0x87, 0xaf ],[ 0x87, 0x87, 0xd7 ],
-[ 0x87, 0x87, 0xff ],[ 0x87, 0xaf, 0x00 ],[ 0x87, 0xaf, 0x5f ],[ 0x87, 0xaf, 0x87 ],[ 0x87, 0xaf, 0xaf ],
-[ 0x87, 0xaf, 0xd7 ],[ 0x87, 0xaf, 0xff ],[ 0x87, 0xd7, 0x00 ],[ 0x87, 0xd7, 0x5f ],[ 0x87, 0xd7, 0x87 ],
-[ 0x87, 0xd7, 0xaf ],[ 0x87, 0xd7, 0xd7 ],[ 0x87, 0xd7, 0xff ],[ 0x87, 0xff, 0x00 ],[ 0x87, 0xff, 0x5f ],
-[ 0x87, 0xff, 0x87 ],[ 0x87, 0xff, 0xaf ],[ 0x87, 0xff, 0xd7 ],[ 0x87, 0xff, 0xff ],[ 0xaf, 0x00, 0x00 ],
-[ 0xaf, 0x00, 0x5f ],[ 0xaf, 0x00, 0x87 ],[ 0xaf, 0x00, 0xaf ],[ 0xaf, 0x00, 0xd7 ],[ 0xaf, 0x00, 0xff ],
-[ 0xaf, 0x5f, 0x00 ],[ 0xaf, 0x5f, 0x5f ],[ 0xaf, 0x5f, 0x87 ],[ 0xaf, 0x5f, 0xaf ],[ 0xaf, 0x5f, 0xd7 ],
-[ 0xaf, 0x5f, 0xff ],[ 0xaf, 0x87, 0x00 ],[ 0xaf, 0x87, 0x5f ],[ 0xaf, 0x87, 0x87 ],[ 0xaf, 0x87, 0xaf ],
-[ 0xaf, 0x87, 0xd7 ],[ 0xaf, 0x87, 0xff ],[ 0xaf, 0xaf, 0x00 ],[ 0xaf, 0xaf, 0x5f ],[ 0xaf, 0xaf, 0x87 ],
-[ 0xaf, 0xaf, 0xaf ],[ 0xaf, 0xaf, 0xd7 ],[ 0xaf, 0xaf, 0xff ],[ 0xaf, 0xd7, 0x00 ],[ 0xaf, 0xd7, 0x5f ],
-[ 0xaf, 0xd7, 0x87 ],[ 0xaf, 0xd7, 0xaf ],[ 0xaf, 0xd7, 0xd7 ],[ 0xaf, 0xd7, 0xff ],[ 0xaf, 0xff, 0x00 ],
-[ 0xaf, 0xff, 0x5f ],[ 0xaf, 0xff, 0x87 ],[ 0xaf, 0xff, 0xaf ],[ 0xaf, 0xff, 0xd7 ],[ 0xaf, 0xff, 0xff ],
-[ 0xd7, 0x00, 0x00 ],[ 0xd7, 0x00, 0x5f ],[ 0xd7, 0x00, 0x87 ],[ 0xd7, 0x00, 0xaf ],[ 0xd7, 0x00, 0xd7 ],
-[ 0xd7, 0x00, 0xff ],[ 0xd7, 0x5f, 0x00 ],[ 0xd7, 0x5f, 0x5f ],[ 0xd7, 0x5f, 0x87 ],[ 0xd7, 0x5f, 0xaf ],
-[ 0xd7, 0x5f, 0xd7 ],[ 0xd7, 0x5f, 0xff ],[ 0xd7, 0x87, 0x00 ],[ 0xd7, 0x87, 0x5f ],[ 0xd7, 0x87, 0x87 ],
-[ 0xd7, 0x87, 0xaf ],[ 0xd7, 0x87, 0xd7 ],[ 0xd7, 0x87, 0xff ],[ 0xd7, 0xaf, 0x00 ],[ 0xd7, 0xaf, 0x5f ],
-[ 0xd7, 0xaf, 0x87 ],[ 0xd7, 0xaf, 0xaf ],[ 0xd7, 0xaf, 0xd7 ],[ 0xd7, 0xaf, 0xff ],[ 0xd7, 0xd7, 0x00 ],
-[ 0xd7, 0xd7, 0x5f ],[ 0xd7, 0xd7, 0x87 ],[ 0xd7, 0xd7, 0xaf ],[ 0xd7, 0xd7, 0xd7 ],[ 0xd7, 0xd7, 0xff ],
-[ 0xd7, 0xff, 0x00 ],[ 0xd7, 0xff, 0x5f ],[ 0xd7, 0xff, 0x87 ],[ 0xd7, 0xff, 0xaf ],[ 0xd7, 0xff, 0xd7 ],
-[ 0xd7, 0xff, 0xff ],[ 0xff, 0x00, 0x00 ],[ 0xff, 0x00, 0x5f ],[ 0xff, 0x00, 0x87 ],[ 0xff, 0x00, 0xaf ],
-[ 0xff, 0x00, 0xd7 ],[ 0xff, 0x00, 0xff ],[ 0xff, 0x5f, 0x00 ],[ 0xff, 0x5f, 0x5f ],[ 0xff, 0x5f, 0x87 ],
-[ 0xff, 0x5f, 0xaf ],[ 0xff, 0x5f, 0xd7 ],[ 0xff, 0x5f, 0xff ],[ 0xff, 0x87, 0x00 ],[ 0xff, 0x87, 0x5f ],
-[ 0xff, 0x87, 0x87 ],[ 0xff, 0x87, 0xaf ],[ 0xff, 0x87, 0xd7 ],[ 0xff, 0x87, 0xff ],[ 0xff, 0xaf, 0x00 ],
-[ 0xff, 0xaf, 0x5f ],[ 0xff, 0xaf, 0x87 ],[ 0xff, 0xaf, 0xaf ],[ 0xff, 0xaf, 0xd7 ],[ 0xff, 0xaf, 0xff ],
-[ 0xff, 0xd7, 0x00 ],[ 0xff, 0xd7, 0x5f ],[ 0xff, 0xd7, 0x87 ],[ 0xff, 0xd7, 0xaf ],[ 0xff, 0xd7, 0xd7 ],
-[ 0xff, 0xd7, 0xff ],[ 0xff, 0xff, 0x00 ],[ 0xff, 0xff, 0x5f ],[ 0xff, 0xff, 0x87 ],[ 0xff, 0xff, 0xaf ],
-[ 0xff, 0xff, 0xd7 ],[ 0xff, 0xff, 0xff ],[ 0x08, 0x08, 0x08 ],[ 0x12, 0x12, 0x12 ],[ 0x1c, 0x1c, 0x1c ],
-[ 0x26, 0x26, 0x26 ],[ 0x30, 0x30, 0x30 ],[ 0x3a, 0x3a, 0x3a ],[ 0x44, 0x44, 0x44 ],[ 0x4e, 0x4e, 0x4e ],
-[ 0x58, 0x58, 0x58 ],[ 0x60, 0x60, 0x60 ],[ 0x66, 0x66, 0x66 ],[ 0x76, 0x76, 0x76 ],[ 0x80, 0x80, 0x80 ],
-[ 0x8a, 0x8a, 0x8a ],[ 0x94, 0x94, 0x94 ],[ 0x9e, 0x9e, 0x9e ],[ 0xa8, 0xa8, 0xa8 ],[ 0xb2, 0xb2, 0xb2 ],
-[ 0xbc, 0xbc, 0xbc ],[ 0xc6, 0xc6, 0xc6 ],[ 0xd0, 0xd0, 0xd0 ],[ 0xda, 0xda, 0xda ],[ 0xe4, 0xe4, 0xe4 ],
-[ 0xee, 0xee, 0xee ]];
+/// Picks the colour of the palette for each pixel of the image, in rows.
+fn quantize(img: &RgbImage, depth: ColorDepth, dither: Dither) -> Vec<u8> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut pixels = img
+        .pixels()
+        .map(|pixel| pixel.0.map(|channel| channel as f32))
+        .collect::<Vec<_>>();
+    // How far apart the colours of the palette are, which is as far as the pattern may nudge.
+    let spread = match depth {
+        ColorDepth::Ansi16 => 128.0,
+        _ => 48.0,
+    };
+    let mut indices = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut colour = pixels[y * width + x];
+            if dither == Dither::Bayer {
+                let nudge = (BAYER[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5;
+                colour = colour.map(|channel| channel + nudge * spread);
+            }
+            let index = palette::nearest(
+                colour.map(|channel| channel.clamp(0.0, 255.0).round() as u8),
+                depth.palette(),
+            );
+            indices.push(index);
+            if dither == Dither::FloydSteinberg {
+                let chosen = ANSI_COLOURS[index as usize];
+                let error = [0, 1, 2].map(|i| colour[i] - chosen[i] as f32);
+                let mut spread_to = |dx: isize, dy: usize, weight: f32| {
+                    let (x, y) = (x as isize + dx, y + dy);
+                    if x >= 0 && (x as usize) < width && y < height {
+                        let pixel = &mut pixels[y * width + x as usize];
+                        for (channel, error) in pixel.iter_mut().zip(error) {
+                            *channel += error * weight;
+                        }
+                    }
+                };
+                spread_to(1, 0, 7.0 / 16.0);
+                spread_to(-1, 1, 3.0 / 16.0);
+                spread_to(0, 1, 5.0 / 16.0);
+                spread_to(1, 1, 1.0 / 16.0);
+            }
+        }
+    }
+    indices
+}
@@ -0,0 +1,61 @@
+//! The table of contents, which is collected from the document's headings before it is printed.
+
+use crate::numbering::HeadingNumbers;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+/// Marks where the table of contents goes, when written as a paragraph of its own.
+const PLACEHOLDER: &str = "[TOC]";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub level: u8,
+    pub text: String,
+    /// The page the heading is on, once the document has been paginated.
+    pub page: Option<usize>,
+}
+
+/// Collects an entry for each of the headings in the document, numbering them the same way as the
+/// headings themselves if requested.
+pub fn entries(source: &str, number_headings: Option<u8>) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut numbers = number_headings.map(HeadingNumbers::new);
+    let mut in_heading = false;
+    for event in Parser::new_ext(source, Options::all()) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                in_heading = true;
+                let number = numbers
+                    .as_mut()
+                    .and_then(|numbers| numbers.next(level as u8));
+                entries.push(Entry {
+                    level: level as u8,
+                    text: number.map(|number| number + " ").unwrap_or_default(),
+                    page: None,
+                });
+            }
+            Event::End(TagEnd::Heading(..)) => in_heading = false,
+            Event::Text(text) | Event::Code(text) if in_heading => {
+                if let Some(entry) = entries.last_mut() {
+                    entry.text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// Whether this is the source of a paragraph that marks where the table of contents goes.
+pub fn is_placeholder(paragraph: &str) -> bool {
+    paragraph.trim() == PLACEHOLDER
+}
+
+/// Whether the document marks where the table of contents goes, rather than leaving it to go at
+/// the top.
+pub fn has_placeholder(source: &str) -> bool {
+    Parser::new_ext(source, Options::all())
+        .into_offset_iter()
+        .any(|(event, range)| {
+            matches!(event, Event::Start(Tag::Paragraph)) && is_placeholder(&source[range])
+        })
+}
@@ -0,0 +1,80 @@
+//! Polls files for changes, so that the paper can be printed again whenever they are edited.
+//! Polling works on any filesystem, including network mounts where change notifications don't.
+
+use crate::images;
+use fnv::FnvHasher;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::fs;
+use std::hash::Hasher as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often to check the files for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<Version>)>,
+}
+
+impl Watcher {
+    /// Starts watching the paths, from their current state.
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            files: paths
+                .into_iter()
+                .map(|path| {
+                    let version = Version::of(&path);
+                    (path, version)
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether any of the files have been modified, created, or removed since this was last
+    /// checked.
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last_version) in &mut self.files {
+            let version = Version::of(path);
+            if version != *last_version {
+                *last_version = version;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// What a file is compared by to tell whether it has changed. The modification time alone would
+/// miss a save that lands within the filesystem's timestamp granularity of the one before it, so
+/// the length and a hash of the contents are compared as well.
+#[derive(PartialEq, Eq)]
+struct Version {
+    modified: SystemTime,
+    len: u64,
+    hash: u64,
+}
+
+impl Version {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mut hasher = FnvHasher::default();
+        hasher.write(&fs::read(path).ok()?);
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+            hash: hasher.finish(),
+        })
+    }
+}
+
+/// The paths of the images that a document refers to, relative to the directory the document is in.
+/// Images embedded in the document are left out.
+pub fn images(markdown: &str, base: Option<&Path>) -> Vec<PathBuf> {
+    Parser::new_ext(markdown, Options::all())
+        .filter_map(|event| match event {
+            Event::Start(Tag::Image { dest_url, .. }) => images::path(&dest_url, base),
+            _ => None,
+        })
+        .collect()
+}
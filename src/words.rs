@@ -99,10 +99,10 @@ impl<S: AsRef<str>> Iterator for Words<S> {
         }
         self.position += start;
         if start == chars.len() {
-            if chars.len() == 0 {
+            if chars.is_empty() {
                 return None;
             } else if self.preserve_whitespace {
-                return Some(chars[..].into_iter().collect());
+                return Some(chars[..].iter().collect());
             } else {
                 return Some(" ".to_string());
             }
@@ -131,14 +131,12 @@ impl<S: AsRef<str>> Iterator for Words<S> {
         self.position += len;
         if chars[0].is_whitespace() {
             if self.preserve_whitespace {
-                return Some(chars[0..start + len].into_iter().collect::<String>());
+                Some(chars[0..start + len].iter().collect::<String>())
             } else {
-                return Some(
-                    String::from(" ") + &chars[start..start + len].into_iter().collect::<String>(),
-                );
+                Some(String::from(" ") + &chars[start..start + len].iter().collect::<String>())
             }
         } else {
-            return Some(chars[start..start + len].into_iter().collect::<String>());
+            Some(chars[start..start + len].iter().collect::<String>())
         }
     }
 }
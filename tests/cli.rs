@@ -0,0 +1,77 @@
+//! Runs the `paper` binary itself, for the behaviour that depends on where its output goes.
+
+use std::process::Command;
+
+#[test]
+fn side_by_side_papers_fit_without_a_terminal() {
+    let path = std::env::temp_dir().join(format!("paper-{}-side.md", std::process::id()));
+    std::fs::write(&path, "# Side\n\nBy side\n").unwrap();
+    // The output is captured through a pipe, so there is no terminal to fit the papers within.
+    let output = Command::new(env!("CARGO_BIN_EXE_paper"))
+        .args(["--side-by-side", "--color", "never"])
+        .arg(&path)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    let row = text.lines().find(|line| line.contains("By side")).unwrap();
+    assert_eq!(row.matches("By side").count(), 2);
+}
+
+#[test]
+fn failing_to_render_is_an_error() {
+    let output = Command::new(env!("CARGO_BIN_EXE_paper"))
+        .args(["--width", "20"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim(),
+        "The width is too short!"
+    );
+}
+
+#[test]
+fn unreadable_files_are_reported_as_errors() {
+    let missing = std::env::temp_dir().join(format!("paper-{}-missing.md", std::process::id()));
+    for side_by_side in [false, true] {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_paper"));
+        command.args(["--color", "never"]).arg(&missing);
+        if side_by_side {
+            command.arg("--side-by-side");
+        }
+        let output = command.output().unwrap();
+        assert!(!output.status.success());
+        let error = String::from_utf8_lossy(&output.stderr);
+        assert!(error.contains(&*missing.to_string_lossy()));
+        let text = String::from_utf8_lossy(&output.stdout);
+        assert!(!text.contains(&*missing.to_string_lossy()));
+    }
+}
+
+#[test]
+fn pdfs_are_not_side_by_side() {
+    let output = Command::new(env!("CARGO_BIN_EXE_paper"))
+        .args(["--side-by-side", "--output-format", "pdf"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--side-by-side"));
+}
+
+#[test]
+fn unreadable_files_fail_exports() {
+    let missing = std::env::temp_dir().join(format!("paper-{}-unexported.md", std::process::id()));
+    for format in ["pdf", "html", "svg"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_paper"))
+            .args(["--output-format", format])
+            .arg(&missing)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{}", format);
+    }
+}
@@ -0,0 +1,61 @@
+use paper_terminal::{RenderOptions, html, pdf, render_lines, render_sheets, svg};
+
+#[test]
+fn html_embeds_images_and_escapes_text() {
+    let source = "# Fish & chips\n\n![A cat](cato.png)\n\n`a < b`\n";
+    let lines = render_lines(source, &RenderOptions::default()).unwrap();
+    let mut out = vec![];
+    html::write_document(&lines, "<menu>", &mut out).unwrap();
+    let page = String::from_utf8(out).unwrap();
+
+    assert!(page.contains("<title>&lt;menu&gt;</title>"));
+    assert!(page.contains("Fish &amp; chips"));
+    assert!(page.contains("a &lt; b"));
+    assert_eq!(page.matches("<img src=\"data:image/png;base64,").count(), 1);
+
+    // The image is embedded at a higher resolution than the cells it covers.
+    let (_, image) = page
+        .split_once("<span class=\"image\" style=\"width: ")
+        .unwrap();
+    let (columns, image) = image
+        .split_once("ch\"><img src=\"data:image/png;base64,")
+        .unwrap();
+    let (data, _) = image.split_once('"').unwrap();
+    let png = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, data).unwrap();
+    let image = image::load_from_memory(&png).unwrap();
+    assert!(image.width() > columns.parse::<u32>().unwrap() * 2);
+}
+
+#[test]
+fn svg_draws_text_and_pixels_on_a_grid() {
+    let source = "Fish & chips\n\n![A cat](cato.png)\n";
+    let opts = RenderOptions::default();
+    let lines = render_lines(source, &opts).unwrap();
+    let mut out = vec![];
+    svg::write_document(&lines, "menu", &mut out).unwrap();
+    let image = String::from_utf8(out).unwrap();
+
+    assert!(image.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(image.contains(">      Fish &amp; chips"));
+    // Each pixel is half of a cell, so the image's rects are half as tall as the line's.
+    assert!(image.contains("height=\"9\""));
+    assert!(image.trim_end().ends_with("</svg>"));
+}
+
+#[test]
+fn pdf_has_a_page_for_each_sheet() {
+    let source = "# Fish & chips\n\n![A cat](cato.png)\n\n| Fish | Chips |\n|------|-------|\n| Cod  | Yes   |\n";
+    let opts = RenderOptions {
+        page_height: Some(pdf::page_height(RenderOptions::default().width)),
+        ..RenderOptions::default()
+    };
+    let sheets = render_sheets(source, &opts).unwrap();
+    let mut out = vec![];
+    pdf::write_document(&sheets, "menu", &mut out).unwrap();
+    let document = String::from_utf8_lossy(&out);
+
+    assert!(document.starts_with("%PDF-"));
+    assert_eq!(document.matches("/Type /Page\n").count(), sheets.len());
+    assert_eq!(document.matches("/Subtype /Image").count(), 1);
+    assert!(document.contains("/FontName /DejaVuSansMono"));
+}
@@ -0,0 +1,285 @@
+use ansi_term::Colour;
+use image::imageops::FilterType;
+use image::{Rgb, RgbImage};
+use paper_terminal::{
+    ColorDepth, Dither, Extent, ImageProtocol, RenderOptions, SpanKind, render, render_lines,
+};
+
+fn draw(protocol: ImageProtocol) -> String {
+    let source = "![A cat](cato.png)\n";
+    let opts = RenderOptions {
+        image_protocol: protocol,
+        ..RenderOptions::default()
+    };
+    let mut out = vec![];
+    render(source, &opts, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn kitty_images_are_sent_in_chunks_without_moving_the_cursor() {
+    let out = draw(ImageProtocol::Kitty);
+    assert!(!out.contains('▄'));
+    assert_eq!(out.matches("\x1b_Ga=T,f=100,q=2,C=1,").count(), 1);
+    let chunks = out.matches("\x1b_G").count();
+    assert_eq!(out.matches("m=1;").count(), chunks - 1);
+    assert_eq!(out.matches("\x1b_Gm=0;").count(), 1);
+}
+
+#[test]
+fn iterm_images_are_drawn_from_the_last_row() {
+    let out = draw(ImageProtocol::Iterm);
+    let start = out.find("\x1b7").unwrap();
+    let image = &out[start..];
+    assert!(image.starts_with("\x1b7\x1b["));
+    assert!(image.contains("D\x1b]1337;File=inline=1;width="));
+    assert!(image.contains(";preserveAspectRatio=0:iVBORw0KGgo"));
+    assert!(image.contains("\x07\x1b8"));
+}
+
+#[test]
+fn sixel_images_are_drawn_in_bands() {
+    let out = draw(ImageProtocol::Sixel);
+    let start = out.find("\x1bP0;1;0q\"1;1;").unwrap();
+    let end = start + out[start..].find("\x1b\\").unwrap();
+    let sixels = &out[start..end];
+    let header = sixels.split('#').next().unwrap();
+    let (width, height) = header["\x1bP0;1;0q\"1;1;".len()..].split_once(';').unwrap();
+    assert!(width.parse::<usize>().unwrap() > 0);
+    // Each band of sixels is six pixels tall.
+    let height = height.parse::<usize>().unwrap();
+    assert_eq!(sixels.matches('-').count(), height.div_ceil(6));
+}
+
+#[test]
+fn limited_colour_depths_use_the_palette() {
+    let source = "# Cats\n\n![A cat](cato.png)\n";
+    let colours = |color_depth| {
+        let opts = RenderOptions {
+            color_depth,
+            ..RenderOptions::default()
+        };
+        render_lines(source, &opts)
+            .unwrap()
+            .into_iter()
+            .flat_map(|line| line.spans)
+            .flat_map(|span| [span.style.foreground, span.style.background])
+            .flatten()
+            .collect::<Vec<_>>()
+    };
+
+    assert!(
+        colours(ColorDepth::TrueColor)
+            .iter()
+            .any(|colour| matches!(colour, Colour::RGB(..)))
+    );
+    assert!(
+        colours(ColorDepth::Ansi256)
+            .iter()
+            .all(|colour| !matches!(colour, Colour::RGB(..)))
+    );
+    assert!(
+        colours(ColorDepth::Ansi16)
+            .iter()
+            .all(|colour| !matches!(colour, Colour::RGB(..) | Colour::Fixed(16..)))
+    );
+}
+
+#[test]
+fn sixteen_colours_are_written_with_their_own_codes() {
+    let source = "# Cats\n\n> quoted\n\n![A cat](cato.png)\n";
+    let opts = RenderOptions {
+        color_depth: ColorDepth::Ansi16,
+        ..RenderOptions::default()
+    };
+    let mut out = vec![];
+    render(source, &opts, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains('▄'));
+    assert!(!text.contains(";5;"));
+    assert!(!text.contains(";2;"));
+    assert!(text.contains("\x1b[9") || text.contains("\x1b[10"));
+
+    // Terminals with more colours are given the bright ones as indices of the 256 colours.
+    let opts = RenderOptions {
+        color_depth: ColorDepth::Ansi256,
+        ..opts
+    };
+    let mut out = vec![];
+    render(source, &opts, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains(";5;8m"));
+    assert!(!text.contains("\x1b[9") && !text.contains("\x1b[10"));
+}
+
+/// Saves an image for a test to draw, returning its path.
+fn save(name: &str, image: RgbImage) -> String {
+    let path = std::env::temp_dir().join(format!("paper-{}-{}.png", std::process::id(), name));
+    image.save(&path).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// The colours of the pixels of each row of the image that is drawn.
+fn pixels(path: &str, opts: RenderOptions) -> Vec<Vec<Colour>> {
+    render_lines(&format!("![]({})\n", path), &opts)
+        .unwrap()
+        .into_iter()
+        .map(|line| {
+            line.spans
+                .into_iter()
+                .filter(|span| span.kind == SpanKind::Image)
+                .filter_map(|span| span.style.background)
+                .collect::<Vec<_>>()
+        })
+        .filter(|row| !row.is_empty())
+        .collect()
+}
+
+#[test]
+fn shrinking_blends_pixels_as_light() {
+    // Alternating black and white lines, shrunk to half their size, average out to the grey that
+    // is half as bright, rather than to the middle sRGB value.
+    let lines = save(
+        "lines",
+        RgbImage::from_fn(160, 160, |_, y| Rgb([if y % 2 == 0 { 0 } else { 255 }; 3])),
+    );
+    for image_filter in [RenderOptions::default().image_filter, FilterType::Triangle] {
+        let opts = RenderOptions {
+            image_filter,
+            ..RenderOptions::default()
+        };
+        let rows = pixels(&lines, opts);
+        assert_eq!(rows[0].len(), 80);
+        let Colour::RGB(r, g, b) = rows[10][10] else {
+            panic!("expected true colour");
+        };
+        assert!(
+            (180..=195).contains(&r) && r == g && g == b,
+            "{image_filter:?}: {r}"
+        );
+    }
+}
+
+#[test]
+fn dithering_breaks_up_bands() {
+    let gradient = save(
+        "gradient",
+        RgbImage::from_fn(80, 8, |x, _| Rgb([(x * 255 / 79) as u8; 3])),
+    );
+    let changes = |dither| {
+        let opts = RenderOptions {
+            color_depth: ColorDepth::Ansi16,
+            dither,
+            ..RenderOptions::default()
+        };
+        let rows = pixels(&gradient, opts);
+        rows[1].windows(2).filter(|pair| pair[0] != pair[1]).count()
+    };
+    let banded = changes(Dither::None);
+    assert!(banded < 5);
+    assert!(changes(Dither::FloydSteinberg) > banded * 4);
+    assert!(changes(Dither::Bayer) > banded * 4);
+}
+
+#[test]
+fn odd_rows_of_pixels_are_drawn() {
+    let stripe = save(
+        "stripe",
+        RgbImage::from_fn(10, 5, |_, y| Rgb([if y == 4 { 255 } else { 0 }; 3])),
+    );
+    let lines = render_lines(&format!("![]({})\n", stripe), &RenderOptions::default()).unwrap();
+    let rows = lines
+        .iter()
+        .filter_map(|line| line.spans.iter().find(|span| span.kind == SpanKind::Image))
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), 3);
+    // The last row is in the bottom half of the last line, which is drawn in the foreground.
+    assert_eq!(rows[2].style.foreground, Some(Colour::RGB(255, 255, 255)));
+}
+
+#[test]
+fn images_are_capped_in_shape() {
+    let square = save("square", RgbImage::from_pixel(40, 40, Rgb([255, 0, 0])));
+    let size = |image_width, image_max_height| {
+        let opts = RenderOptions {
+            image_width,
+            image_max_height,
+            ..RenderOptions::default()
+        };
+        let rows = pixels(&square, opts);
+        (rows[0].len(), rows.len())
+    };
+    assert_eq!(size(None, None), (40, 20));
+    assert_eq!(size(None, Some(10)), (20, 10));
+    assert_eq!(size(Some(Extent::Cells(20)), None), (20, 10));
+    assert_eq!(
+        size(Some(Extent::Percent(50)), Some(100)).1 * 2,
+        size(Some(Extent::Percent(50)), None).0
+    );
+}
+
+#[test]
+fn images_are_shaped_by_the_cell_size() {
+    let square = save(
+        "square-cells",
+        RgbImage::from_pixel(40, 40, Rgb([0, 255, 0])),
+    );
+    let size = |cell_size| {
+        let opts = RenderOptions {
+            cell_size,
+            ..RenderOptions::default()
+        };
+        let rows = pixels(&square, opts);
+        (rows[0].len(), rows.len())
+    };
+    assert_eq!(size(paper_terminal::DEFAULT_CELL_SIZE), (40, 20));
+    assert_eq!(size((10, 10)), (40, 40));
+}
+
+#[test]
+fn images_are_found_beside_the_document() {
+    let dot = save("dot", RgbImage::from_pixel(4, 4, Rgb([0, 0, 255])));
+    let dot = std::path::Path::new(&dot);
+    let opts = RenderOptions {
+        path: Some(dot.with_file_name("document.md")),
+        ..RenderOptions::default()
+    };
+    let mut png = std::io::Cursor::new(vec![]);
+    RgbImage::from_pixel(4, 4, Rgb([0, 0, 255]))
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    let data = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, png.into_inner());
+    for url in [
+        dot.file_name().unwrap().to_string_lossy().into_owned(),
+        format!("file://{}", dot.display()),
+        format!("data:image/png;base64,{}", data),
+    ] {
+        let rows = pixels(&url, opts.clone());
+        assert_eq!(rows, vec![vec![Colour::RGB(0, 0, 255); 4]; 2], "{url}");
+    }
+}
+
+#[test]
+fn svg_images_are_drawn_at_their_size_on_the_paper() {
+    // A thin line, which would be lost between the pixels if the image were drawn at its own size
+    // and then shrunk.
+    let path = std::env::temp_dir().join(format!("paper-{}-line.svg", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="400" height="200">
+            <rect width="400" height="200" fill="white"/>
+            <rect x="201" y="0" width="4" height="200" fill="black"/>
+        </svg>"#,
+    )
+    .unwrap();
+    let opts = RenderOptions {
+        image_width: Some(Extent::Cells(40)),
+        ..RenderOptions::default()
+    };
+    let rows = pixels(&path.to_string_lossy(), opts);
+    assert_eq!((rows[0].len(), rows.len()), (40, 10));
+    assert!(rows.iter().all(|row| {
+        row.iter()
+            .any(|colour| matches!(colour, Colour::RGB(r, _, _) if *r < 224))
+    }));
+}
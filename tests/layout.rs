@@ -0,0 +1,231 @@
+use paper_terminal::{Filter, Line, RenderOptions, SpanKind, render_lines, render_side_by_side};
+use std::collections::HashMap;
+
+#[test]
+fn lines_are_rectangular() {
+    let source = "# Title\n\n* A list item that is long enough to wrap around onto the following line\n\n| a | b |\n| - | - |\n| c | d |\n";
+    let lines = render_lines(source, &RenderOptions::default()).unwrap();
+    let body = &lines[1..lines.len() - 1];
+    assert!(body.iter().all(|line| line.width() == body[0].width()));
+}
+
+#[test]
+fn spans_know_their_source() {
+    let source = "Hello\n\n> *quoted*\n";
+    let lines = render_lines(source, &RenderOptions::default()).unwrap();
+    let spans = lines.iter().flat_map(|line| &line.spans);
+
+    let hello = spans.clone().find(|span| span.text == "Hello").unwrap();
+    assert_eq!(hello.kind, SpanKind::Content);
+    assert_eq!(hello.offset, Some(0));
+
+    let quoted = spans.clone().find(|span| span.text == "quoted").unwrap();
+    assert_eq!(quoted.kind, SpanKind::Content);
+    assert_eq!(quoted.offset, Some(source.find("quoted").unwrap()));
+    assert!(quoted.style.is_italic);
+
+    let marker = spans.clone().find(|span| span.text == "┃   ").unwrap();
+    assert_eq!(marker.kind, SpanKind::Prefix);
+    assert_eq!(marker.offset, None);
+}
+
+#[test]
+fn side_by_side_papers_line_up() {
+    let opts = RenderOptions {
+        width: 60,
+        terminal_width: 130,
+        ..RenderOptions::default()
+    };
+    let short = "Short";
+    let long = "Long\n\nwith\n\nseveral\n\nparagraphs\n";
+    let lines = render_side_by_side(&[(short, opts.clone()), (long, opts.clone())]).unwrap();
+    assert_eq!(lines.len(), render_lines(long, &opts).unwrap().len());
+    assert!(lines.iter().all(|line| line.width() == lines[0].width()));
+
+    let row = lines
+        .iter()
+        .find(|line| line.text().contains("Short"))
+        .unwrap();
+    assert!(row.text().contains("Long"));
+}
+
+#[cfg(unix)]
+#[test]
+fn filters_replace_code_blocks() {
+    let source = "```shout\nhello\n```\n";
+    let filter = |replace| RenderOptions {
+        filters: [(
+            String::from("shout"),
+            Filter {
+                command: String::from("tr a-z A-Z"),
+                replace,
+            },
+        )]
+        .into(),
+        ..RenderOptions::default()
+    };
+
+    let framed = render_lines(source, &filter(false)).unwrap();
+    let text = framed.iter().map(Line::text).collect::<Vec<_>>();
+    assert!(text.iter().any(|line| line.contains("HELLO")));
+    assert!(text.iter().any(|line| line.contains("shout")));
+
+    let replaced = render_lines(source, &filter(true)).unwrap();
+    let text = replaced.iter().map(Line::text).collect::<Vec<_>>();
+    assert!(text.iter().any(|line| line.contains("HELLO")));
+    assert!(!text.iter().any(|line| line.contains("shout")));
+}
+
+#[test]
+fn uncoloured_papers_keep_their_layout() {
+    let source = "# Title\n\n* item\n\n| a | b |\n| - | - |\n| c | d |\n\n![A cat](cato.png)\n";
+    let opts = RenderOptions {
+        color: false,
+        ..RenderOptions::default()
+    };
+    let mut out = vec![];
+    paper_terminal::render(source, &opts, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+
+    assert!(!text.contains('\x1b'));
+    assert!(!text.contains('▄'));
+    assert!(text.contains("• "));
+    assert!(text.contains("│ a │ b │"));
+}
+
+#[test]
+fn running_text_is_written_without_a_margin() {
+    let source = "First\n\nSecond\n\nThird\n\nFourth\n";
+    let opts = RenderOptions {
+        v_margin: 0,
+        page_height: Some(6),
+        header: "{file}|Draft".parse().unwrap(),
+        path: Some("notes.md".into()),
+        ..RenderOptions::default()
+    };
+    let sheets = paper_terminal::render_sheets(source, &opts).unwrap();
+    assert_eq!(sheets.len(), 3);
+    for (index, sheet) in sheets.iter().enumerate() {
+        let text = sheet.iter().map(Line::text).collect::<Vec<_>>();
+        assert_eq!(text.len(), 7);
+        assert!(text[1].contains("notes.md") && text[1].contains("Draft"));
+        assert!(text[5].contains(&format!("Page {} of 3", index + 1)));
+    }
+}
+
+#[test]
+fn columns_are_balanced_on_each_page() {
+    let source = (1..=30)
+        .map(|n| format!("Paragraph {}\n\n", n))
+        .collect::<String>();
+    let opts = RenderOptions {
+        columns: 2,
+        page_height: Some(20),
+        terminal_width: 200,
+        ..RenderOptions::default()
+    };
+    let sheets = paper_terminal::render_sheets(&source, &opts).unwrap();
+    let mut order = vec![];
+    for sheet in &sheets {
+        let mut columns = [vec![], vec![]];
+        for line in sheet {
+            let text = line.text();
+            for (start, _) in text.match_indices("Paragraph ") {
+                let number = text[start + 10..]
+                    .split_whitespace()
+                    .next()
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap();
+                columns[usize::from(start > text.len() / 2)].push(number);
+            }
+        }
+        assert!(!columns[1].is_empty());
+        order.extend(columns.concat());
+    }
+    assert_eq!(order, (1..=30).collect::<Vec<_>>());
+}
+
+#[test]
+fn skipped_heading_levels_keep_headings_numbered_uniquely() {
+    let source = "# One\n\n### Skipped\n\n## Sub\n\n# Two\n\n## Two one\n";
+    let opts = RenderOptions {
+        number_headings: Some(1),
+        ..RenderOptions::default()
+    };
+    let text = render_lines(source, &opts)
+        .unwrap()
+        .iter()
+        .map(Line::text)
+        .collect::<String>();
+    assert!(text.contains("1. One"));
+    assert!(text.contains("1.0.1. Skipped"));
+    assert!(text.contains("1.1. Sub"));
+    assert!(text.contains("2. Two"));
+    assert!(text.contains("2.1. Two one"));
+}
+
+#[cfg(feature = "highlight")]
+#[test]
+fn code_block_attributes_are_not_part_of_the_language() {
+    let opts = RenderOptions {
+        highlight: true,
+        ..RenderOptions::default()
+    };
+    let plain = render_lines("```rust\nfn main() {}\n```\n", &opts).unwrap();
+    let attributed = render_lines("```rust ignore\nfn main() {}\n```\n", &opts).unwrap();
+    assert_eq!(plain, attributed);
+}
+
+#[cfg(unix)]
+#[test]
+fn nested_code_blocks_are_filtered() {
+    let source = "\
+* item
+
+  ```shout rust
+  one
+  ```
+
+> ```shout
+> two
+> ```
+
+Term
+: ```shout
+  three
+  ```
+
+10. ten
+
+    ```whisper
+    FOUR
+    ```
+";
+    let filters: HashMap<_, _> = [
+        ("shout", "tr a-z A-Z", false),
+        ("whisper", "tr A-Z a-z", true),
+    ]
+    .into_iter()
+    .map(|(lang, command, replace)| {
+        let command = String::from(command);
+        (String::from(lang), Filter { command, replace })
+    })
+    .collect();
+    for columns in [1, 2] {
+        let opts = RenderOptions {
+            columns,
+            terminal_width: 200,
+            filters: filters.clone(),
+            ..RenderOptions::default()
+        };
+        let text = render_lines(source, &opts)
+            .unwrap()
+            .iter()
+            .map(Line::text)
+            .collect::<String>();
+        for filtered in ["ONE", "TWO", "THREE", "four"] {
+            assert!(text.contains(filtered), "{filtered} in {columns} columns");
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Renders the documents in `tests/snapshots` and compares them byte for byte against the expected
+//! output checked in beside them. Run with `UPDATE_SNAPSHOTS=1` to regenerate the expected output.
+
+use paper_terminal::{RenderOptions, render};
+use std::fs;
+use std::path::PathBuf;
+
+fn snapshot(name: &str, variant: &str, opts: &RenderOptions) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let source = fs::read_to_string(dir.join(format!("{}.md", name))).unwrap();
+    let mut output = vec![];
+    render(&source, opts, &mut output).unwrap();
+
+    let expected_path = dir.join(format!("{}.{}.ansi", name, variant));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&expected_path, &output).unwrap();
+        return;
+    }
+    let expected = fs::read(&expected_path).unwrap_or_else(|error| {
+        panic!(
+            "Missing snapshot {}: {}. Run with UPDATE_SNAPSHOTS=1 to create it.",
+            expected_path.display(),
+            error
+        )
+    });
+    assert!(
+        output == expected,
+        "Snapshot {} does not match.\n\nExpected:\n{}\nActual:\n{}",
+        expected_path.display(),
+        String::from_utf8_lossy(&expected),
+        String::from_utf8_lossy(&output),
+    );
+}
+
+fn at_width(width: usize) -> RenderOptions {
+    RenderOptions {
+        width,
+        terminal_width: width + 1,
+        ..RenderOptions::default()
+    }
+}
+
+macro_rules! snapshots {
+    ($($test:ident: $name:literal at $($width:literal),+;)*) => {
+        $(
+            #[test]
+            fn $test() {
+                $(snapshot($name, stringify!($width), &at_width($width));)+
+            }
+        )*
+    };
+}
+
+snapshots! {
+    headings: "headings" at 60, 92;
+    alerts: "alerts" at 60, 92;
+    lists: "lists" at 60, 92;
+    tables: "tables" at 60, 92;
+    footnotes: "footnotes" at 60, 92;
+    code: "code" at 60, 92;
+}
+
+#[test]
+fn plain() {
+    snapshot(
+        "lists",
+        "plain",
+        &RenderOptions {
+            plain: true,
+            ..at_width(72)
+        },
+    );
+}
+
+#[test]
+fn paginated() {
+    snapshot(
+        "lists",
+        "paginated",
+        &RenderOptions {
+            page_height: Some(20),
+            ..at_width(60)
+        },
+    );
+}
+
+#[test]
+fn columns() {
+    snapshot(
+        "headings",
+        "columns",
+        &RenderOptions {
+            columns: 2,
+            terminal_width: 200,
+            position: paper_terminal::Position::Left,
+            ..at_width(60)
+        },
+    );
+}
+
+#[test]
+fn toc() {
+    snapshot(
+        "toc",
+        "paginated",
+        &RenderOptions {
+            toc: true,
+            page_height: Some(24),
+            ..at_width(60)
+        },
+    );
+}
+
+#[cfg(feature = "highlight")]
+#[test]
+fn highlighted() {
+    snapshot(
+        "code",
+        "highlighted",
+        &RenderOptions {
+            highlight: true,
+            ..at_width(60)
+        },
+    );
+}
+
+#[test]
+fn numbered_headings() {
+    snapshot(
+        "headings",
+        "numbered",
+        &RenderOptions {
+            number_headings: Some(2),
+            ..at_width(60)
+        },
+    );
+}
+
+#[test]
+fn running_headers() {
+    snapshot(
+        "headings",
+        "running",
+        &RenderOptions {
+            page_height: Some(24),
+            header: "{file}||{heading}".parse().unwrap(),
+            footer: "Page {page} of {pages}".parse().unwrap(),
+            path: Some(PathBuf::from("docs/headings.md")),
+            ..at_width(60)
+        },
+    );
+}
@@ -0,0 +1,45 @@
+use paper_terminal::watch::Watcher;
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn changes_to_files_are_noticed() {
+    let path = std::env::temp_dir().join(format!("paper-{}-watched.md", std::process::id()));
+    fs::write(&path, "Before\n").unwrap();
+    let mut watcher = Watcher::new([path.clone()]);
+    assert!(!watcher.changed());
+
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+
+    fs::remove_file(&path).unwrap();
+    assert!(watcher.changed());
+}
+
+#[test]
+fn changes_within_the_same_modification_time_are_noticed() {
+    let path = std::env::temp_dir().join(format!("paper-{}-resaved.md", std::process::id()));
+    fs::write(&path, "Before\n").unwrap();
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    let mut watcher = Watcher::new([path.clone()]);
+
+    // Saved again so quickly that the filesystem gives it the same time.
+    for text in ["Beside\n", "Beside, and after\n"] {
+        fs::write(&path, text).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(watcher.changed(), "{}", text);
+    }
+
+    fs::remove_file(&path).unwrap();
+}